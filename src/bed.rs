@@ -0,0 +1,408 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::Command;
+
+use rustc_hash::FxHashMap;
+
+use cov_viz_ds::facets::FACET_DIRECTION;
+use cov_viz_ds::{BucketLoc, DbID, ObservationData};
+
+use crate::bucket_summary::BucketSummary;
+use crate::build_data::{BuildOutput, FeatureInfo};
+use crate::manifest::StagedFiles;
+use crate::options::Options;
+
+const BED_TYPE: &str = "bed6+2";
+const FEATURE_AUTOSQL: &str = r#"table covVizFeature
+"Source or target feature of a regulatory effect observation"
+    (
+    string chrom;            "Reference sequence chromosome or scaffold"
+    uint   chromStart;       "Start position in chromosome"
+    uint   chromEnd;         "End position in chromosome"
+    string name;             "Feature id"
+    uint   score;            "Best -log10 significance, scaled to 0-1000"
+    char[1] strand;          "+ or - or . for unknown"
+    double bestSignificance; "Smallest significance of any observation of the feature"
+    lstring facets;          "Comma-separated facet values of the feature"
+    )
+"#;
+
+const ENRICHED_COLOR: &str = "215,48,39";
+const DEPLETED_COLOR: &str = "69,117,180";
+const NONSIGNIFICANT_COLOR: &str = "160,160,160";
+
+// Scale a -log10 significance to the 0-1000 BED score range, relative to the most
+// significant observation in the analysis.
+pub fn bed_score(neg_log_significance: f64, max_neg_log_significance: f64) -> u32 {
+    if max_neg_log_significance <= 0.0 {
+        return 0;
+    }
+    ((neg_log_significance / max_neg_log_significance) * 1000.0)
+        .round()
+        .clamp(0.0, 1000.0) as u32
+}
+
+// Writes the sources and targets as BED, per-bucket observation counts as bedGraph, and,
+// if requested, converts the BED files to BigBed using the UCSC `bedToBigBed` tool.
+pub fn export_bed(
+    output: &BuildOutput,
+    options: &Options,
+    staged: &mut StagedFiles,
+) -> io::Result<()> {
+    let observations: Vec<&ObservationData> = output
+        .coverage
+        .significant_observations
+        .iter()
+        .chain(output.coverage.nonsignificant_observations.iter())
+        .collect();
+    let max_neg_log_significance = observations
+        .iter()
+        .map(|o| o.neg_log_significance)
+        .fold(0.0, f64::max);
+    let facet_names = output.facet_value_names();
+
+    // feature id -> (best significance, best -log10 significance)
+    let mut source_significance: FxHashMap<DbID, (f64, f64)> = FxHashMap::default();
+    let mut target_significance: FxHashMap<DbID, (f64, f64)> = FxHashMap::default();
+    for observation in &observations {
+        let best = (observation.significance, observation.neg_log_significance);
+        source_significance
+            .entry(observation.source_id)
+            .and_modify(|e| *e = min_significance(*e, best))
+            .or_insert(best);
+        if let Some(target_id) = observation.target_id {
+            target_significance
+                .entry(target_id)
+                .and_modify(|e| *e = min_significance(*e, best))
+                .or_insert(best);
+        }
+    }
+
+    // The features are written on their own sequences, so with --contigs=other this lists the
+    // contigs rather than the pseudo-chromosome
+    let chrom_sizes_path = staged.stage(&options.export_path("chrom.sizes"));
+    let mut chrom_sizes = BufWriter::new(File::create(&chrom_sizes_path)?);
+    for (name, length) in &output.sequences {
+        writeln!(chrom_sizes, "{}\t{}", name, length)?;
+    }
+    chrom_sizes.flush()?;
+
+    let mut bed_paths = Vec::new();
+    for (name, significance) in [
+        ("sources", &source_significance),
+        ("targets", &target_significance),
+    ] {
+        let bed_path = staged.stage(&options.export_path(&format!("{}.bed", name)));
+        write_feature_bed(
+            &bed_path,
+            output,
+            significance,
+            &facet_names,
+            max_neg_log_significance,
+        )?;
+        bed_paths.push((name, bed_path));
+    }
+
+    if options.bigbed_export {
+        let autosql_path = staged.stage(&options.export_path("features.as"));
+        std::fs::write(&autosql_path, FEATURE_AUTOSQL)?;
+        for (name, bed_path) in bed_paths {
+            bed_to_bigbed(
+                &bed_path,
+                &autosql_path,
+                &chrom_sizes_path,
+                &staged.stage(&options.export_path(&format!("{}.bb", name))),
+            )?;
+        }
+    }
+
+    for (name, buckets) in [
+        ("sources", &output.summary.source_buckets),
+        ("targets", &output.summary.target_buckets),
+    ] {
+        write_bucket_bedgraph(
+            &staged.stage(&options.export_path(&format!("{}.bedGraph", name))),
+            output,
+            buckets,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn min_significance(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    if b.0 < a.0 {
+        b
+    } else {
+        a
+    }
+}
+
+fn write_feature_bed(
+    path: &Path,
+    output: &BuildOutput,
+    significance: &FxHashMap<DbID, (f64, f64)>,
+    facet_names: &FxHashMap<DbID, &str>,
+    max_neg_log_significance: f64,
+) -> io::Result<()> {
+    let mut features: Vec<(&DbID, &FeatureInfo, &(f64, f64))> = output
+        .feature_info
+        .iter()
+        .filter_map(|(id, info)| significance.get(id).map(|sig| (id, info, sig)))
+        .collect();
+    // bedToBigBed wants the same order as `sort -k1,1 -k2,2n`
+    features.sort_by(|a, b| {
+        output.sequences[a.1.sequence as usize]
+            .0
+            .cmp(&output.sequences[b.1.sequence as usize].0)
+            .then(a.1.start.cmp(&b.1.start))
+            .then(a.0.cmp(b.0))
+    });
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (id, info, (best_significance, best_neg_log_significance)) in features {
+        let mut facets: Vec<&str> = info
+            .facet_value_ids
+            .iter()
+            .filter_map(|facet_id| facet_names.get(facet_id).cloned())
+            .collect();
+        facets.sort_unstable();
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            output.sequences[info.sequence as usize].0,
+            info.start,
+            info.end,
+            id,
+            bed_score(*best_neg_log_significance, max_neg_log_significance),
+            info.strand.unwrap_or('.'),
+            best_significance,
+            if facets.is_empty() {
+                ".".to_string()
+            } else {
+                facets.join(",")
+            },
+        )?;
+    }
+
+    writer.flush()
+}
+
+fn write_bucket_bedgraph(
+    path: &Path,
+    output: &BuildOutput,
+    buckets: &FxHashMap<BucketLoc, BucketSummary>,
+) -> io::Result<()> {
+    let bucket_size = output.coverage.bucket_size;
+    let mut buckets: Vec<(&BucketLoc, &BucketSummary)> = buckets.iter().collect();
+    buckets.sort_by(|a, b| {
+        output.chrom_names[a.0.chrom as usize]
+            .cmp(&output.chrom_names[b.0.chrom as usize])
+            .then(a.0.idx.cmp(&b.0.idx))
+    });
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (bucket, summary) in buckets {
+        let chrom_length = output.coverage.chrom_lengths[bucket.chrom as usize] as u64;
+        let start = bucket.idx as u64 * bucket_size as u64;
+        let end = (start + bucket_size as u64).min(chrom_length);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            output.chrom_names[bucket.chrom as usize], start, end, summary.observation_count
+        )?;
+    }
+
+    writer.flush()
+}
+
+fn bed_to_bigbed(
+    bed_path: &Path,
+    autosql_path: &Path,
+    chrom_sizes_path: &Path,
+    bigbed_path: &Path,
+) -> io::Result<()> {
+    // The facets column joins facet values that can contain spaces (e.g., "Enriched Only"), so
+    // bedToBigBed has to split on tabs only
+    let status = Command::new("bedToBigBed")
+        .arg("-tab")
+        .arg(format!("-type={}", BED_TYPE))
+        .arg(format!("-as={}", autosql_path.display()))
+        .arg(bed_path)
+        .arg(chrom_sizes_path)
+        .arg(bigbed_path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "bedToBigBed failed for {}: {}",
+            bed_path.display(),
+            status
+        )));
+    }
+
+    Ok(())
+}
+
+// Item color for an observation, based on its direction facet value
+fn direction_color(
+    observation: &ObservationData,
+    direction_values: &FxHashMap<DbID, String>,
+) -> &'static str {
+    let direction = observation
+        .facet_value_ids
+        .iter()
+        .find_map(|id| direction_values.get(id));
+    match direction {
+        Some(value) if value.contains("Enriched") => ENRICHED_COLOR,
+        Some(value) if value.contains("Depleted") => DEPLETED_COLOR,
+        _ => NONSIGNIFICANT_COLOR,
+    }
+}
+
+// An observation with a target, along with its source and target features
+type Link<'a> = (&'a ObservationData, &'a FeatureInfo, &'a FeatureInfo);
+
+// Writes every observation with a target as a source -> target link, as BEDPE and/or
+// as a UCSC interact track.
+pub fn export_links(
+    output: &BuildOutput,
+    options: &Options,
+    staged: &mut StagedFiles,
+) -> io::Result<()> {
+    let mut observations: Vec<Link> = output
+        .coverage
+        .significant_observations
+        .iter()
+        .chain(output.coverage.nonsignificant_observations.iter())
+        .filter_map(|o| {
+            let source = output.feature_info.get(&o.source_id)?;
+            let target = output.feature_info.get(&o.target_id?)?;
+            Some((o, source, target))
+        })
+        .collect();
+    observations.sort_by(|a, b| {
+        output.sequences[a.1.sequence as usize]
+            .0
+            .cmp(&output.sequences[b.1.sequence as usize].0)
+            .then(a.1.start.cmp(&b.1.start))
+            .then(a.0.reo_id.cmp(&b.0.reo_id))
+    });
+
+    let max_neg_log_significance = observations
+        .iter()
+        .map(|o| o.0.neg_log_significance)
+        .fold(0.0, f64::max);
+    let empty_values = FxHashMap::default();
+    let direction_values = output
+        .coverage
+        .facets
+        .iter()
+        .find(|f| f.name == FACET_DIRECTION)
+        .and_then(|f| f.values.as_ref())
+        .unwrap_or(&empty_values);
+
+    if options.bedpe_export {
+        let mut writer = BufWriter::new(File::create(
+            staged.stage(&options.export_path("observations.bedpe")),
+        )?);
+        writeln!(
+            writer,
+            "#chrom1\tstart1\tend1\tchrom2\tstart2\tend2\tname\tscore\tstrand1\tstrand2\tcolor\teffect_size\tsignificance\tdistance"
+        )?;
+        for (observation, source, target) in &observations {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                output.sequences[source.sequence as usize].0,
+                source.start,
+                source.end,
+                output.sequences[target.sequence as usize].0,
+                target.start,
+                target.end,
+                observation.reo_id,
+                bed_score(observation.neg_log_significance, max_neg_log_significance),
+                source.strand.unwrap_or('.'),
+                target.strand.unwrap_or('.'),
+                direction_color(observation, direction_values),
+                observation.effect_size,
+                observation.significance,
+                // Trans links have no distance
+                output
+                    .observation_distances
+                    .get(&(observation.reo_id, observation.source_id))
+                    .map(|distance| distance.to_string())
+                    .unwrap_or(".".to_string()),
+            )?;
+        }
+        writer.flush()?;
+    }
+
+    if options.interact_export {
+        let mut writer = BufWriter::new(File::create(
+            staged.stage(&options.export_path("observations.interact")),
+        )?);
+        writeln!(
+            writer,
+            "track type=interact name=\"{}\" description=\"{} observations\" useScore=on",
+            options.analysis_accession_id, options.analysis_accession_id
+        )?;
+        // (chrom, start, end) of each record, along with the observation it's for. For cis
+        // links the record spans both ends. The interact format needs interchromosomal links
+        // written twice, once at each end, so they show up on both chromosomes.
+        let mut records: Vec<(&str, u32, u32, &Link)> = Vec::with_capacity(observations.len());
+        for link in &observations {
+            let (_, source, target) = link;
+            if source.sequence == target.sequence {
+                records.push((
+                    &output.sequences[source.sequence as usize].0,
+                    source.start.min(target.start),
+                    source.end.max(target.end),
+                    link,
+                ));
+            } else {
+                for anchor in [source, target] {
+                    records.push((
+                        &output.sequences[anchor.sequence as usize].0,
+                        anchor.start,
+                        anchor.end,
+                        link,
+                    ));
+                }
+            }
+        }
+        records.sort_by(|a, b| {
+            a.0.cmp(b.0)
+                .then(a.1.cmp(&b.1))
+                .then(a.3 .0.reo_id.cmp(&b.3 .0.reo_id))
+        });
+
+        for (chrom, start, end, (observation, source, target)) in records {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                chrom,
+                start,
+                end,
+                observation.reo_id,
+                bed_score(observation.neg_log_significance, max_neg_log_significance),
+                observation.effect_size,
+                options.analysis_accession_id,
+                direction_color(observation, direction_values),
+                output.sequences[source.sequence as usize].0,
+                source.start,
+                source.end,
+                observation.source_id,
+                source.strand.unwrap_or('.'),
+                output.sequences[target.sequence as usize].0,
+                target.start,
+                target.end,
+                observation.target_id.unwrap(),
+                target.strand.unwrap_or('.'),
+            )?;
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
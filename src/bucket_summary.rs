@@ -0,0 +1,218 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+
+use cov_viz_ds::{BucketLoc, DbID, ObservationData};
+
+// Aggregate of all the observations whose source (or target) falls in a bucket
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BucketSummary {
+    pub observation_count: u64,
+    // direction facet value id -> observation count
+    pub direction_counts: FxHashMap<DbID, u64>,
+    pub min_effect_size: f32,
+    pub max_effect_size: f32,
+    pub mean_effect_size: f32,
+    pub max_neg_log_significance: f64,
+    pub source_count: u64,
+    pub target_count: u64,
+}
+
+// Precomputed per-bucket summaries that let the genome-wide overview render without
+// the full list of observations. Written next to the coverage data rather than in it
+// so the overview can be loaded on its own.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CoverageSummary {
+    pub bucket_size: u32,
+    pub source_buckets: FxHashMap<BucketLoc, BucketSummary>,
+    pub target_buckets: FxHashMap<BucketLoc, BucketSummary>,
+}
+
+#[derive(Default)]
+struct BucketAccumulator {
+    observation_count: u64,
+    direction_counts: FxHashMap<DbID, u64>,
+    min_effect_size: f32,
+    max_effect_size: f32,
+    effect_size_total: f64,
+    max_neg_log_significance: f64,
+    sources: FxHashSet<DbID>,
+    targets: FxHashSet<DbID>,
+}
+
+impl BucketAccumulator {
+    fn add(&mut self, observation: &ObservationData, direction_value_ids: &FxHashSet<DbID>) {
+        if self.observation_count == 0 {
+            self.min_effect_size = observation.effect_size;
+            self.max_effect_size = observation.effect_size;
+        } else {
+            self.min_effect_size = self.min_effect_size.min(observation.effect_size);
+            self.max_effect_size = self.max_effect_size.max(observation.effect_size);
+        }
+        self.observation_count += 1;
+        self.effect_size_total += observation.effect_size as f64;
+        self.max_neg_log_significance = self
+            .max_neg_log_significance
+            .max(observation.neg_log_significance);
+
+        for facet_value_id in observation
+            .facet_value_ids
+            .iter()
+            .filter(|id| direction_value_ids.contains(id))
+        {
+            *self.direction_counts.entry(*facet_value_id).or_insert(0) += 1;
+        }
+
+        self.sources.insert(observation.source_id);
+        if let Some(target_id) = observation.target_id {
+            self.targets.insert(target_id);
+        }
+    }
+
+    fn finish(self) -> BucketSummary {
+        BucketSummary {
+            observation_count: self.observation_count,
+            direction_counts: self.direction_counts,
+            min_effect_size: self.min_effect_size,
+            max_effect_size: self.max_effect_size,
+            mean_effect_size: (self.effect_size_total / self.observation_count as f64) as f32,
+            max_neg_log_significance: self.max_neg_log_significance,
+            source_count: self.sources.len() as u64,
+            target_count: self.targets.len() as u64,
+        }
+    }
+}
+
+pub fn summarize<'a>(
+    observations: impl Iterator<Item = &'a ObservationData>,
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    direction_value_ids: &FxHashSet<DbID>,
+    bucket_size: u32,
+) -> CoverageSummary {
+    let mut source_buckets: FxHashMap<BucketLoc, BucketAccumulator> = FxHashMap::default();
+    let mut target_buckets: FxHashMap<BucketLoc, BucketAccumulator> = FxHashMap::default();
+
+    for observation in observations {
+        if let Some(bucket) = feature_buckets.get(&observation.source_id) {
+            source_buckets
+                .entry(*bucket)
+                .or_default()
+                .add(observation, direction_value_ids);
+        }
+        if let Some(bucket) = observation
+            .target_id
+            .and_then(|target_id| feature_buckets.get(&target_id))
+        {
+            target_buckets
+                .entry(*bucket)
+                .or_default()
+                .add(observation, direction_value_ids);
+        }
+    }
+
+    CoverageSummary {
+        bucket_size,
+        source_buckets: source_buckets
+            .into_iter()
+            .map(|(bucket, acc)| (bucket, acc.finish()))
+            .collect(),
+        target_buckets: target_buckets
+            .into_iter()
+            .map(|(bucket, acc)| (bucket, acc.finish()))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(
+        source_id: DbID,
+        target_id: Option<DbID>,
+        facet_value_ids: Vec<DbID>,
+        effect_size: f32,
+        neg_log_significance: f64,
+    ) -> ObservationData {
+        ObservationData {
+            reo_id: 1,
+            facet_value_ids,
+            source_id,
+            target_id,
+            effect_size,
+            significance: 10f64.powf(-neg_log_significance),
+            neg_log_significance,
+        }
+    }
+
+    fn bucket(chrom: u8, idx: u32) -> BucketLoc {
+        BucketLoc { chrom, idx }
+    }
+
+    #[test]
+    fn summarizes_source_and_target_buckets() {
+        let feature_buckets: FxHashMap<DbID, BucketLoc> = [
+            (10, bucket(0, 0)),
+            (11, bucket(0, 0)),
+            (12, bucket(0, 1)),
+            (20, bucket(1, 5)),
+        ]
+        .into_iter()
+        .collect();
+        // 100 and 101 are direction values, 200 isn't
+        let direction_value_ids: FxHashSet<DbID> = [100, 101].into_iter().collect();
+        let observations = [
+            observation(10, Some(20), vec![100, 200], 1.0, 2.0),
+            observation(11, Some(20), vec![101], -3.0, 5.0),
+            observation(10, None, vec![100], 2.0, 1.0),
+            observation(12, None, vec![], 0.5, 0.5),
+            // A source that didn't get a bucket is left out
+            observation(13, None, vec![100], 9.0, 9.0),
+        ];
+
+        let summary = summarize(
+            observations.iter(),
+            &feature_buckets,
+            &direction_value_ids,
+            1000,
+        );
+        assert_eq!(summary.bucket_size, 1000);
+        assert_eq!(summary.source_buckets.len(), 2);
+        assert_eq!(summary.target_buckets.len(), 1);
+
+        let first = &summary.source_buckets[&bucket(0, 0)];
+        assert_eq!(first.observation_count, 3);
+        assert_eq!(first.direction_counts.len(), 2);
+        assert_eq!(first.direction_counts[&100], 2);
+        assert_eq!(first.direction_counts[&101], 1);
+        assert_eq!(first.min_effect_size, -3.0);
+        assert_eq!(first.max_effect_size, 2.0);
+        assert_eq!(first.mean_effect_size, 0.0);
+        assert_eq!(first.max_neg_log_significance, 5.0);
+        assert_eq!(first.source_count, 2);
+        assert_eq!(first.target_count, 1);
+
+        let second = &summary.source_buckets[&bucket(0, 1)];
+        assert_eq!(second.observation_count, 1);
+        assert!(second.direction_counts.is_empty());
+        assert_eq!((second.min_effect_size, second.max_effect_size), (0.5, 0.5));
+        assert_eq!(second.target_count, 0);
+
+        let target = &summary.target_buckets[&bucket(1, 5)];
+        assert_eq!(target.observation_count, 2);
+        assert_eq!(target.mean_effect_size, -1.0);
+        assert_eq!(target.source_count, 2);
+        assert_eq!(target.target_count, 1);
+    }
+
+    #[test]
+    fn summarizes_nothing() {
+        let summary = summarize(
+            std::iter::empty(),
+            &FxHashMap::default(),
+            &FxHashSet::default(),
+            1000,
+        );
+        assert!(summary.source_buckets.is_empty());
+        assert!(summary.target_buckets.is_empty());
+    }
+}
@@ -1,20 +1,117 @@
-use std::time::Instant;
+use std::collections::BTreeMap;
 
 use postgres::types::Json;
-use postgres::{Client, Error};
+use postgres::Client;
 use postgres_range::Range;
 use roaring::RoaringTreemap;
 use rustc_hash::{FxHashMap, FxHashSet};
+use serde_json::json;
 
+use crate::bucket_summary::{summarize, CoverageSummary};
+use crate::build_stats::{
+    BuildStats, FacetValueCoverage, DROPPED_INVALID_LOCATION, DROPPED_OUT_OF_BOUNDS,
+    DROPPED_UNKNOWN_SOURCE_CHROMOSOME, DROPPED_UNKNOWN_TARGET_CHROMOSOME, DROPPED_UNLIFTED,
+};
+use crate::contigs::{Chromosomes, ContigPlacement, ContigPolicy};
+use crate::coordinates::{check_bounds, BoundsCheck, BoundsPolicy, GenomicInterval};
+use crate::error::BuildError;
+use crate::facet_stats::{
+    facet_value_counts, value_range, ExperimentFacetStats, FacetHistogram, FacetStats,
+    HistogramScale, HISTOGRAM_BIN_COUNT,
+};
+use crate::interaction_matrix::{interaction_matrix, InteractionMatrix};
+use crate::liftover::{Liftover, UnliftedFeature};
+use crate::logging::Logger;
 use crate::options::Options;
+use crate::regions::{region_columns, RegionIndex};
 
 use cov_viz_ds::facets::{
-    facet_set, FACET_CCRE_CATEGORY, FACET_CCRE_OVERLAP, FACET_DIRECTION, FACET_EFFECT_SIZE,
-    FACET_GRNA_TYPE, FACET_SIGNIFICANCE, FACET_TYPE_CATEGORICAL,
+    facet_set, FacetCoverage, FACET_CCRE_CATEGORY, FACET_CCRE_OVERLAP, FACET_DIRECTION,
+    FACET_EFFECT_SIZE, FACET_GRNA_TYPE, FACET_SIGNIFICANCE, FACET_TYPE_CATEGORICAL,
 };
 use cov_viz_ds::*;
 
 pub const MIN_SIG: f64 = 1e-100;
+// Synthetic facets, which cov_viz adds to the ones in the database:
+// The analysis an observation comes from, when several analyses are built together
+pub const FACET_ANALYSIS: &str = "Analysis";
+// Whether an observation has a target; source-only screens (e.g., proliferation screens) don't
+pub const FACET_HAS_TARGET: &str = "Has Target";
+pub const HAS_TARGET_YES: &str = "Yes";
+pub const HAS_TARGET_NO: &str = "No";
+// Whether an observation's source and target are on the same sequence
+pub const FACET_INTERACTION: &str = "Interaction";
+pub const INTERACTION_CIS: &str = "Cis";
+pub const INTERACTION_TRANS: &str = "Trans";
+// How far the middle of a cis observation's source is from its target's TSS. This was asked for
+// as a numeric facet, but an ObservationData only carries categorical facet value ids (plus the
+// effect size and significance), so a numeric distance the viewer can filter on needs a new
+// ObservationData field in cov_viz_ds and a bump of the pinned rev. Until then the distance is
+// binned into a categorical facet; the exact distance is only in the BEDPE and observation table
+// exports.
+pub const FACET_DISTANCE: &str = "Distance";
+// (smallest distance in the bin, in bases, value)
+pub const DISTANCE_BINS: [(u32, &str); 5] = [
+    (0, "Under 1 kb"),
+    (1_000, "1-10 kb"),
+    (10_000, "10-100 kb"),
+    (100_000, "100 kb-1 Mb"),
+    (1_000_000, "Over 1 Mb"),
+];
+
+// Synthetic facets and their values have fixed ids, so they're the same from build to build and
+// never collide with the database's. Each facet gets a block of SYNTHETIC_ID_BLOCK ids starting
+// at its own id, and its values take the ids after it, in order. The blocks start far past
+// anything a Postgres serial column reaches, but low enough to be exact as JavaScript numbers.
+pub const SYNTHETIC_ID_BASE: DbID = 1 << 40;
+const SYNTHETIC_ID_BLOCK: DbID = 1 << 20;
+pub const FACET_HAS_TARGET_ID: DbID = SYNTHETIC_ID_BASE;
+pub const FACET_INTERACTION_ID: DbID = SYNTHETIC_ID_BASE + SYNTHETIC_ID_BLOCK;
+pub const FACET_DISTANCE_ID: DbID = SYNTHETIC_ID_BASE + 2 * SYNTHETIC_ID_BLOCK;
+pub const FACET_ANALYSIS_ID: DbID = SYNTHETIC_ID_BASE + 3 * SYNTHETIC_ID_BLOCK;
+
+// Facets build_data can't run without
+pub const REQUIRED_FACETS: [&str; 4] = [
+    FACET_DIRECTION,
+    FACET_CCRE_OVERLAP,
+    FACET_CCRE_CATEGORY,
+    FACET_GRNA_TYPE,
+];
+
+// Selects the REOs with a source or target on one of the chromosomes in $2, for level 2
+// builds and the dry run's estimate of them. The EXISTS subqueries keep each REO to a single
+// row however many of its sources and targets are on the chromosomes.
+pub const REO_ON_CHROMOSOMES: &str = r#"(
+            EXISTS (
+                SELECT 1
+                FROM search_regulatoryeffectobservation_sources as re_s
+                INNER JOIN search_dnafeature as sf ON (sf.id = re_s.dnafeature_id)
+                WHERE re_s.regulatoryeffectobservation_id = search_regulatoryeffectobservation.id and sf.chrom_name = ANY($2)
+            ) or EXISTS (
+                SELECT 1
+                FROM search_regulatoryeffectobservation_targets as re_t
+                INNER JOIN search_dnafeature as tf ON (tf.id = re_t.dnafeature_id)
+                WHERE re_t.regulatoryeffectobservation_id = search_regulatoryeffectobservation.id and tf.chrom_name = ANY($2)
+            )
+        )"#;
+
+// REOs with a source or target overlapping one of the regions given by $2 (chromosome names),
+// $3 (0-based starts), and $4 (0-based, exclusive ends), as region_columns builds them
+pub const REO_IN_REGIONS: &str = r#"(
+            EXISTS (
+                SELECT 1
+                FROM search_regulatoryeffectobservation_sources as re_s
+                INNER JOIN search_dnafeature as sf ON (sf.id = re_s.dnafeature_id)
+                INNER JOIN unnest($2::text[], $3::int4[], $4::int4[]) as region(chrom_name, start_pos, end_pos) ON (sf.chrom_name = region.chrom_name and sf.location && int4range(region.start_pos, region.end_pos))
+                WHERE re_s.regulatoryeffectobservation_id = search_regulatoryeffectobservation.id
+            ) or EXISTS (
+                SELECT 1
+                FROM search_regulatoryeffectobservation_targets as re_t
+                INNER JOIN search_dnafeature as tf ON (tf.id = re_t.dnafeature_id)
+                INNER JOIN unnest($2::text[], $3::int4[], $4::int4[]) as region(chrom_name, start_pos, end_pos) ON (tf.chrom_name = region.chrom_name and tf.location && int4range(region.start_pos, region.end_pos))
+                WHERE re_t.regulatoryeffectobservation_id = search_regulatoryeffectobservation.id
+            )
+        )"#;
 
 const GRCH38: [(&str, i32, u8); 25] = [
     ("1", 248956422, 0),
@@ -71,31 +168,237 @@ const GRCH37: [(&str, i32, u8); 25] = [
     ("MT", 16569, 24),
 ];
 
-fn select_assembly<'a>(assembly_name: &'a str) -> Vec<(&'static str, i32, u8)> {
+// Where a source or target is, along with its own categorical facet values. The coverage data
+// only keeps feature buckets, but the genome browser exports need actual coordinates. These
+// are on the feature's own sequence (an index into BuildOutput::sequences), so a contig in the
+// "other" pseudo-chromosome keeps its name and coordinates.
+#[derive(Clone, Debug)]
+pub struct FeatureInfo {
+    pub sequence: u32,
+    pub start: u32,
+    pub end: u32,
+    pub strand: Option<char>,
+    pub facet_value_ids: Vec<DbID>,
+}
+
+// (chrom name, location, lifted to the opposite strand)
+type LiftedLocation<'a> = (&'a str, Option<Range<i32>>, bool);
+
+pub struct BuildOutput {
+    pub coverage: CoverageData,
+    pub features: ExperimentFeatureData,
+    pub facet_stats: ExperimentFacetStats,
+    pub summary: CoverageSummary,
+    pub interactions: InteractionMatrix,
+    pub feature_info: FxHashMap<DbID, FeatureInfo>,
+    // (re id, source id) -> distance from the middle of the source to the target's TSS, for
+    // cis observations. ObservationData has no room for it, so it's kept alongside.
+    pub observation_distances: FxHashMap<(DbID, DbID), u32>,
+    pub chrom_names: Vec<String>,
+    // (name as in the database, length) for every sequence in feature_info
+    pub sequences: Vec<(String, i32)>,
+    pub stats: BuildStats,
+    // Only filled in with --liftover
+    pub unlifted_features: Vec<UnliftedFeature>,
+}
+
+impl BuildOutput {
+    // facet value id -> value, for every facet value used in the coverage data
+    pub fn facet_value_names(&self) -> FxHashMap<DbID, &str> {
+        self.coverage
+            .facets
+            .iter()
+            .filter_map(|facet| facet.values.as_ref())
+            .flatten()
+            .map(|(id, value)| (*id, value.as_str()))
+            .collect()
+    }
+}
+
+pub fn select_assembly(assembly_name: &str) -> Result<Vec<(&'static str, i32, u8)>, BuildError> {
     match assembly_name {
-        "GRCH37" => GRCH37.to_vec(),
-        "GRCH38" => GRCH38.to_vec(),
-        _ => {
-            eprintln!(
-                "Invalid genome {}. Must be \"GRCH37\" or \"GRCH38\"",
-                assembly_name
+        "GRCH37" => Ok(GRCH37.to_vec()),
+        "GRCH38" => Ok(GRCH38.to_vec()),
+        _ => Err(BuildError::Config(format!(
+            "Invalid genome {}. Must be \"GRCH37\" or \"GRCH38\"",
+            assembly_name
+        ))),
+    }
+}
+
+fn find_facet<'a>(facets: &'a [Facet], name: &str) -> Result<&'a Facet, BuildError> {
+    facets
+        .iter()
+        .find(|f| f.name == name)
+        .ok_or_else(|| BuildError::Data(format!("Facet \"{}\" is missing", name)))
+}
+
+// Adds a facet that isn't in the database, with one of the reserved synthetic facet ids. Returns
+// the value ids, in the same order as `values`.
+fn add_synthetic_facet(
+    all_facets: &mut Vec<Facet>,
+    all_facet_values: &mut Vec<FacetValue>,
+    facet_id: DbID,
+    name: &str,
+    description: &str,
+    facet_type: &str,
+    values: &[&str],
+) -> Vec<DbID> {
+    all_facets.push(Facet {
+        id: facet_id,
+        name: name.to_string(),
+        description: description.to_string(),
+        facet_type: facet_type.to_string(),
+        coverage: None,
+        range: None,
+        range64: None,
+        values: None,
+    });
+    let value_ids: Vec<DbID> = (facet_id + 1..).take(values.len()).collect();
+    all_facet_values.extend(
+        values
+            .iter()
+            .zip(&value_ids)
+            .map(|(value, value_id)| FacetValue {
+                id: *value_id,
+                value: value.to_string(),
+                facet_id,
+            }),
+    );
+
+    value_ids
+}
+
+// Converts a source or target's location to 0-based, half-open coordinates and checks it against
+// its chromosome, following the --out-of-bounds policy. Empty and unbounded locations are
+// handled the same way as out of bounds ones, except that they can't be clipped. Returns the
+// reason if the feature's REO should be dropped. The coordinates returned are shifted by the
+// contig's offset in its chromosome.
+fn bounded_location(
+    kind: &str,
+    feature_id: DbID,
+    location: Option<&Range<i32>>,
+    placement: ContigPlacement,
+    options: &Options,
+    logger: &Logger,
+    stats: &mut BuildStats,
+) -> Result<Result<(u32, u32), &'static str>, BuildError> {
+    let (start, end) = match GenomicInterval::from_location(location) {
+        Ok(interval) => interval.half_open(),
+        Err(e) if options.bounds_policy == BoundsPolicy::Fail => {
+            return Err(BuildError::Data(format!("{} {}: {}", kind, feature_id, e)))
+        }
+        Err(e) => {
+            logger.debug(
+                "Invalid location",
+                json!({ "kind": kind, "id": feature_id, "problem": e }),
+            );
+            return Ok(Err(DROPPED_INVALID_LOCATION));
+        }
+    };
+    let chrom_length = placement.length;
+    match check_bounds(start, end, chrom_length, options.bounds_policy)
+        .map_err(|e| BuildError::Data(format!("{} {}: {}", kind, feature_id, e)))?
+    {
+        BoundsCheck::InBounds(start, end) => Ok(Ok((
+            start + placement.offset as u32,
+            end + placement.offset as u32,
+        ))),
+        BoundsCheck::Clipped(clipped_start, clipped_end) => {
+            logger.debug(
+                "Clipped coordinates to chromosome",
+                json!({
+                    "kind": kind,
+                    "id": feature_id,
+                    "start": start,
+                    "end": end,
+                    "chrom_length": chrom_length,
+                }),
+            );
+            stats.clipped_features += 1;
+            Ok(Ok((
+                clipped_start + placement.offset as u32,
+                clipped_end + placement.offset as u32,
+            )))
+        }
+        BoundsCheck::Dropped => {
+            logger.debug(
+                "Coordinates outside chromosome",
+                json!({
+                    "kind": kind,
+                    "id": feature_id,
+                    "start": start,
+                    "end": end,
+                    "chrom_length": chrom_length,
+                }),
             );
-            panic!();
+            Ok(Err(DROPPED_OUT_OF_BOUNDS))
         }
     }
 }
 
+// A target's TSS: its first base, or its last on the "-" strand. The end is exclusive, so the
+// last base is the one before it.
+fn target_tss(strand: &str, start: u32, end: u32) -> u32 {
+    match strand {
+        "-" => end - 1,
+        _ => start,
+    }
+}
+
+// The distance from the middle of a source (0-based, half-open) to its target's TSS, if they're
+// on the same sequence (a cis link), or None for a trans link
+fn cis_distance(
+    source_chrom_name: &str,
+    start: u32,
+    end: u32,
+    (target_chrom_name, tss): (&str, u32),
+) -> Option<u32> {
+    (source_chrom_name == target_chrom_name).then(|| (start + (end - start) / 2).abs_diff(tss))
+}
+
+// The DISTANCE_BINS index of the bin a cis distance falls in: the last bin whose smallest
+// distance is at most `distance`
+fn distance_bin(distance: u32) -> usize {
+    DISTANCE_BINS.partition_point(|(min, _)| *min <= distance) - 1
+}
+
 pub fn build_data(
     options: &Options,
     client: &mut Client,
-) -> Result<(CoverageData, ExperimentFeatureData), Error> {
+    logger: &Logger,
+) -> Result<BuildOutput, BuildError> {
     let bucket = |size: u32| size / options.bucket_size;
 
-    let assembly_info = select_assembly(&options.assembly_name);
+    let assembly_info = select_assembly(&options.output_assembly_name)?;
+    let liftover = match &options.liftover_chain {
+        Some(path) => {
+            let liftover = Liftover::load(path).map_err(|e| {
+                BuildError::Config(format!(
+                    "Unable to read chain file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            logger.info(
+                "Loaded chain file",
+                json!({
+                    "path": path,
+                    "input_assembly": options.assembly_name,
+                    "output_assembly": options.output_assembly_name,
+                }),
+            );
+            Some(liftover)
+        }
+        None => None,
+    };
 
     let mut significant_observations: Vec<ObservationData> = Vec::new();
     let mut nonsignificant_observations: Vec<ObservationData> = Vec::new();
     let mut feature_buckets = FxHashMap::<DbID, BucketLoc>::default();
+    let mut feature_info = FxHashMap::<DbID, FeatureInfo>::default();
+    // (re id, source id) -> distance, for cis observations
+    let mut observation_distances: FxHashMap<(DbID, DbID), u32> = FxHashMap::default();
     let mut source_set = RoaringTreemap::default();
     let mut target_set = RoaringTreemap::default();
 
@@ -104,11 +407,9 @@ pub fn build_data(
         chrom_keys.insert(info.0, info.2);
     }
 
-    let chrom_data: Vec<ChromosomeData> = assembly_info
-        .iter()
-        .map(|chrom| ChromosomeData::from(chrom.0, chrom.2))
-        .collect();
+    let mut stats = BuildStats::default();
 
+    let phase = logger.phase("facet load");
     let all_facet_rows = client.query(
         "SELECT id, name, description, facet_type FROM search_facet",
         &[],
@@ -129,7 +430,7 @@ pub fn build_data(
 
     let all_facet_value_rows =
         client.query("SELECT id, value, facet_id FROM search_facetvalue", &[])?;
-    let all_facet_values: Vec<FacetValue> = all_facet_value_rows
+    let mut all_facet_values: Vec<FacetValue> = all_facet_value_rows
         .iter()
         .map(|r| FacetValue {
             id: r.get::<&str, i64>("id") as DbID,
@@ -137,6 +438,88 @@ pub fn build_data(
             facet_id: r.get::<&str, i64>("facet_id") as DbID,
         })
         .collect();
+    if let Some(id) = all_facets
+        .iter()
+        .map(|f| f.id)
+        .chain(all_facet_values.iter().map(|v| v.id))
+        .find(|id| *id >= SYNTHETIC_ID_BASE)
+    {
+        return Err(BuildError::Data(format!(
+            "Facet or facet value id {} is in the range reserved for synthetic facets",
+            id
+        )));
+    }
+
+    // Synthetic facets describe the whole observation, and are always kept in the output
+    let mut synthetic_facet_ids: FxHashSet<DbID> = FxHashSet::default();
+
+    // Target-less observations are tagged explicitly, so the viewer doesn't have to infer them
+    // from a missing target and can filter them out
+    let has_target_value_ids = add_synthetic_facet(
+        &mut all_facets,
+        &mut all_facet_values,
+        FACET_HAS_TARGET_ID,
+        FACET_HAS_TARGET,
+        "Whether the observation has a target",
+        FACET_TYPE_CATEGORICAL,
+        &[HAS_TARGET_YES, HAS_TARGET_NO],
+    );
+    synthetic_facet_ids.insert(FACET_HAS_TARGET_ID);
+    let (has_target_value, no_target_value) = (has_target_value_ids[0], has_target_value_ids[1]);
+
+    // Observations with a target are cis or trans, and cis ones are also binned by distance, so
+    // users can tell short range, long range, and interchromosomal links apart
+    let interaction_value_ids = add_synthetic_facet(
+        &mut all_facets,
+        &mut all_facet_values,
+        FACET_INTERACTION_ID,
+        FACET_INTERACTION,
+        "Whether the observation's source and target are on the same chromosome",
+        FACET_TYPE_CATEGORICAL,
+        &[INTERACTION_CIS, INTERACTION_TRANS],
+    );
+    synthetic_facet_ids.insert(FACET_INTERACTION_ID);
+    let (cis_value, trans_value) = (interaction_value_ids[0], interaction_value_ids[1]);
+    let distance_value_ids = add_synthetic_facet(
+        &mut all_facets,
+        &mut all_facet_values,
+        FACET_DISTANCE_ID,
+        FACET_DISTANCE,
+        "Distance from the middle of the source to the target's TSS, for cis observations",
+        FACET_TYPE_CATEGORICAL,
+        &DISTANCE_BINS.map(|(_, value)| value),
+    );
+    synthetic_facet_ids.insert(FACET_DISTANCE_ID);
+
+    // When several analyses are merged, each observation is tagged with its analysis so the
+    // viewer can filter and color by analysis
+    // accession id -> facet value id
+    let mut analysis_value_ids: FxHashMap<&str, DbID> = FxHashMap::default();
+    if options.analysis_accession_ids.len() > 1 {
+        let accession_ids: Vec<&str> = options
+            .analysis_accession_ids
+            .iter()
+            .map(|id| id.as_str())
+            .collect();
+        let value_ids = add_synthetic_facet(
+            &mut all_facets,
+            &mut all_facet_values,
+            FACET_ANALYSIS_ID,
+            FACET_ANALYSIS,
+            "The analysis the observation comes from",
+            FACET_TYPE_CATEGORICAL,
+            &accession_ids,
+        );
+        synthetic_facet_ids.insert(FACET_ANALYSIS_ID);
+        analysis_value_ids.extend(accession_ids.into_iter().zip(value_ids));
+    }
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({
+            "facets": all_facets.len(),
+            "facet_values": all_facet_values.len(),
+        })),
+    );
 
     // (re id: DbID, facet value id: DbID, value: &str, facet id: DbID)
     let facet_values_statement = client.prepare(r#"
@@ -159,34 +542,24 @@ pub fn build_data(
         INNER JOIN search_regulatoryeffectobservation_targets ON (search_dnafeature.id = search_regulatoryeffectobservation_targets.dnafeature_id)
         WHERE search_regulatoryeffectobservation_targets.regulatoryeffectobservation_id = ANY($1)"#
     )?;
-    // (source id: DbID, facet value id: DbID, value: &str, facet id: DbID)
-    let source_facet_statement = client.prepare(r#"
+    // (feature id: DbID, facet value id: DbID, value: &str, facet id: DbID)
+    let feature_facet_statement = client.prepare(r#"
         SELECT (search_dnafeature_facet_values.dnafeature_id) AS _prefetch_related_val_dnafeature_id, search_facetvalue.id, search_facetvalue.value, search_facetvalue.facet_id
         FROM search_facetvalue
         INNER JOIN search_dnafeature_facet_values ON (search_facetvalue.id = search_dnafeature_facet_values.facetvalue_id)
         WHERE search_dnafeature_facet_values.dnafeature_id = ANY($1)"#
     )?;
+    // Only used for the analysis-wide ranges; the ranges written to the coverage data come
+    // from the observations themselves.
     let facet_range_statement = client.prepare(r#"
         SELECT MIN(((search_regulatoryeffectobservation.facet_num_values -> $1))::double precision) AS min, MAX(((search_regulatoryeffectobservation.facet_num_values -> $1))::double precision) AS max
         FROM search_regulatoryeffectobservation
-        WHERE search_regulatoryeffectobservation.analysis_accession_id = $2"#
+        WHERE search_regulatoryeffectobservation.analysis_accession_id = ANY($2)"#
     )?;
-    let dir_facet = all_facets
-        .iter()
-        .find(|f| f.name == FACET_DIRECTION)
-        .unwrap();
-    let ccre_overlap_facet = all_facets
-        .iter()
-        .find(|f| f.name == FACET_CCRE_OVERLAP)
-        .unwrap();
-    let ccre_category_facet = all_facets
-        .iter()
-        .find(|f| f.name == FACET_CCRE_CATEGORY)
-        .unwrap();
-    let grna_type_facet = all_facets
-        .iter()
-        .find(|f| f.name == FACET_GRNA_TYPE)
-        .unwrap();
+    let dir_facet = find_facet(&all_facets, FACET_DIRECTION)?;
+    let ccre_overlap_facet = find_facet(&all_facets, FACET_CCRE_OVERLAP)?;
+    let ccre_category_facet = find_facet(&all_facets, FACET_CCRE_CATEGORY)?;
+    let grna_type_facet = find_facet(&all_facets, FACET_GRNA_TYPE)?;
     let source_facet_ids: FxHashSet<DbID> = FxHashSet::from_iter(
         [
             ccre_overlap_facet.id,
@@ -198,41 +571,84 @@ pub fn build_data(
 
     let mut facet_ids: FxHashSet<DbID> = FxHashSet::default();
 
-    let re_start_time = Instant::now();
-
-    // (id: DbID, numeric facets: Json)
+    let phase = logger.phase("REO query");
+    // (id: DbID, numeric facets: Json, analysis accession id: &str)
     let reg_effects_statement = client.prepare(r#"
-        SELECT search_regulatoryeffectobservation.id, search_regulatoryeffectobservation.facet_num_values
+        SELECT search_regulatoryeffectobservation.id, search_regulatoryeffectobservation.facet_num_values, search_regulatoryeffectobservation.analysis_accession_id
         FROM search_regulatoryeffectobservation
-        WHERE search_regulatoryeffectobservation.analysis_accession_id = $1"#
+        WHERE search_regulatoryeffectobservation.analysis_accession_id = ANY($1)"#
     )?;
-    let reg_effects_chromo_statement = client.prepare(r#"
-        SELECT search_regulatoryeffectobservation.id, search_regulatoryeffectobservation.facet_num_values
+    // The same REOs a level 1 build has, limited to those with a source or target on the
+    // chromosome
+    let reg_effects_chromo_statement = client.prepare(&format!(
+        r#"
+        SELECT search_regulatoryeffectobservation.id, search_regulatoryeffectobservation.facet_num_values, search_regulatoryeffectobservation.analysis_accession_id
         FROM search_regulatoryeffectobservation
-        INNER JOIN search_regulatoryeffectobservation_sources as re_s ON (search_regulatoryeffectobservation.id = re_s.regulatoryeffectobservation_id)
-        INNER JOIN search_dnafeature as sf ON (sf.id = re_s.dnafeature_id)
-        INNER JOIN search_regulatoryeffectobservation_targets as re_t ON (search_regulatoryeffectobservation.id = re_t.regulatoryeffectobservation_id)
-        INNER JOIN search_dnafeature as tf ON (tf.id = re_t.dnafeature_id)
-        WHERE search_regulatoryeffectobservation.analysis_accession_id = $1 and (sf.chrom_name = $2 or tf.chrom_name = $2)"#
-    )?;
+        WHERE search_regulatoryeffectobservation.analysis_accession_id = ANY($1) and {}"#,
+        REO_ON_CHROMOSOMES
+    ))?;
+    // Region builds without a liftover only fetch the REOs in the regions. Their exact
+    // selection (only the first target counts, and invalid locations never do) is still made
+    // below, along with the region builds whose regions are in the lifted coordinates.
+    let reg_effects_regions_statement = client.prepare(&format!(
+        r#"
+        SELECT search_regulatoryeffectobservation.id, search_regulatoryeffectobservation.facet_num_values, search_regulatoryeffectobservation.analysis_accession_id
+        FROM search_regulatoryeffectobservation
+        WHERE search_regulatoryeffectobservation.analysis_accession_id = ANY($1) and {}"#,
+        REO_IN_REGIONS
+    ))?;
     let reg_effects = match &options.chromo {
-        None => client.query(&reg_effects_statement, &[&options.analysis_accession_id])?,
         Some(chromo) => client.query(
             &reg_effects_chromo_statement,
-            &[&options.analysis_accession_id, &chromo],
+            &[&options.analysis_accession_ids, &vec![chromo]],
         )?,
+        None if !options.regions.is_empty() && options.liftover_chain.is_none() => {
+            let (chrom_names, starts, ends) = region_columns(&options.regions);
+            client.query(
+                &reg_effects_regions_statement,
+                &[
+                    &options.analysis_accession_ids,
+                    &chrom_names,
+                    &starts,
+                    &ends,
+                ],
+            )?
+        }
+        None => client.query(&reg_effects_statement, &[&options.analysis_accession_ids])?,
     };
     let mut reg_effect_num_facets: FxHashMap<DbID, FxHashMap<&str, f32>> = FxHashMap::default();
+    // re id -> analysis facet value id
+    let mut reg_effect_analysis: FxHashMap<DbID, DbID> = FxHashMap::default();
     for row in &reg_effects {
         let key = row.get::<usize, i64>(0) as DbID;
-        let value = row.get::<usize, Json<FxHashMap<&str, f32>>>(1).0;
-        reg_effect_num_facets.insert(key, value);
+        let value = row
+            .try_get::<usize, Json<FxHashMap<&str, f32>>>(1)
+            .map_err(|e| {
+                BuildError::Data(format!("REO {} has invalid numeric facets: {}", key, e))
+            })?
+            .0;
+        // Processing an REO twice would duplicate its observations
+        if reg_effect_num_facets.insert(key, value).is_some() {
+            return Err(BuildError::Data(format!(
+                "REO {} was returned more than once by the REO query",
+                key
+            )));
+        }
+        if let Some(value_id) = analysis_value_ids.get(row.get::<usize, &str>(2)) {
+            reg_effect_analysis.insert(key, *value_id);
+        }
     }
 
     let reg_effect_id_list = reg_effects
         .iter()
         .map(|row| row.get::<&str, i64>("id"))
         .collect::<Vec<i64>>();
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({ "reos": reg_effect_id_list.len() })),
+    );
+
+    let phase = logger.phase("facet value query");
     let facet_values = client.query(&facet_values_statement, &[&reg_effect_id_list])?;
     // (re id: DbID, facet value id: DbID, value: &str, facet id: DbID)
     let mut facet_values_dict: FxHashMap<DbID, Vec<(DbID, &str, DbID)>> = FxHashMap::default();
@@ -248,42 +664,61 @@ pub fn build_data(
             .and_modify(|e| e.push(value))
             .or_insert(vec![value]);
     }
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({ "rows": facet_values.len() })),
+    );
 
+    let phase = logger.phase("source/target queries");
     let sources = client.query(&re_sources_statement, &[&reg_effect_id_list])?;
     let mut source_dict: FxHashMap<
         DbID,
-        Vec<(DbID, Option<Json<FxHashMap<&str, f32>>>, &str, Range<i32>)>,
+        Vec<(
+            DbID,
+            Option<Json<FxHashMap<&str, f32>>>,
+            &str,
+            Option<Range<i32>>,
+        )>,
     > = FxHashMap::default();
     for row in &sources {
         let key = row.get::<usize, i64>(0) as DbID;
-        source_dict
-            .entry(key)
-            .and_modify(|e| {
-                e.push((
-                    row.get::<usize, i64>(1) as DbID,
-                    row.get::<usize, Option<Json<FxHashMap<&str, f32>>>>(2),
-                    row.get::<usize, &str>(3),
-                    row.get::<usize, Range<i32>>(4),
+        let source_id = row.get::<usize, i64>(1) as DbID;
+        let num_facets = row
+            .try_get::<usize, Option<Json<FxHashMap<&str, f32>>>>(2)
+            .map_err(|e| {
+                BuildError::Data(format!(
+                    "Source {} has invalid numeric facets: {}",
+                    source_id, e
                 ))
-            })
-            .or_insert(vec![(
-                row.get::<usize, i64>(1) as DbID,
-                row.get::<usize, Option<Json<FxHashMap<&str, f32>>>>(2),
-                row.get::<usize, &str>(3),
-                row.get::<usize, Range<i32>>(4),
-            )]);
+            })?;
+        // A NULL location is handled like any other invalid one, following --out-of-bounds,
+        // but the source can't be placed at all without a chromosome
+        let chrom_name = row
+            .try_get::<usize, Option<&str>>(3)?
+            .ok_or_else(|| BuildError::Data(format!("Source {} has no chromosome", source_id)))?;
+        source_dict.entry(key).or_default().push((
+            source_id,
+            num_facets,
+            chrom_name,
+            row.try_get::<usize, Option<Range<i32>>>(4)?,
+        ));
     }
 
     let targets = client.query(&re_targets_statement, &[&reg_effect_id_list])?;
-    let mut target_dict: FxHashMap<DbID, Vec<(DbID, &str, Range<i32>, &str)>> =
+    let mut target_dict: FxHashMap<DbID, Vec<(DbID, &str, Option<Range<i32>>, &str)>> =
         FxHashMap::default();
     for row in &targets {
         let key = row.get::<usize, i64>(0) as DbID;
+        let target_id = row.get::<usize, i64>(1) as DbID;
+        let missing =
+            |column: &str| BuildError::Data(format!("Target {} has no {}", target_id, column));
         let value = (
-            row.get::<usize, i64>(1) as DbID,
-            row.get::<usize, &str>(2),
-            row.get::<usize, Range<i32>>(3),
-            row.get::<usize, &str>(4),
+            target_id,
+            row.try_get::<usize, Option<&str>>(2)?
+                .ok_or_else(|| missing("chromosome"))?,
+            row.try_get::<usize, Option<Range<i32>>>(3)?,
+            row.try_get::<usize, Option<&str>>(4)?
+                .ok_or_else(|| missing("strand"))?,
         );
         target_dict
             .entry(key)
@@ -295,7 +730,7 @@ pub fn build_data(
         .iter()
         .map(|row| row.get::<&str, i64>("id"))
         .collect::<Vec<i64>>();
-    let source_facets = client.query(&source_facet_statement, &[&source_id_list])?;
+    let source_facets = client.query(&feature_facet_statement, &[&source_id_list])?;
     let mut source_facet_dict: FxHashMap<DbID, Vec<(DbID, &str, DbID)>> = FxHashMap::default();
     for row in &source_facets {
         let key = row.get::<usize, i64>(0) as DbID;
@@ -310,28 +745,345 @@ pub fn build_data(
             .or_insert(vec![value]);
     }
 
-    println!("Regulatory Effect count: {}", reg_effect_id_list.len());
+    let target_id_list = targets
+        .iter()
+        .map(|row| row.get::<&str, i64>("id"))
+        .collect::<Vec<i64>>();
+    let target_facets = client.query(&feature_facet_statement, &[&target_id_list])?;
+    let mut target_facet_dict: FxHashMap<DbID, Vec<(DbID, &str, DbID)>> = FxHashMap::default();
+    for row in &target_facets {
+        let key = row.get::<usize, i64>(0) as DbID;
+        let value = (
+            row.get::<usize, i64>(1) as DbID,
+            row.get::<usize, &str>(2),
+            row.get::<usize, i64>(3) as DbID,
+        );
+        target_facet_dict
+            .entry(key)
+            .and_modify(|e| e.push(value))
+            .or_insert(vec![value]);
+    }
+
+    // Target facets aren't a fixed list like the source facets -- any categorical facet used to
+    // annotate the targets (gene biotype, gene type, etc.) is something the viewer can filter on.
+    let target_facet_ids: FxHashSet<DbID> = target_facet_dict
+        .values()
+        .flatten()
+        .map(|f| f.2)
+        .filter(|facet_id| !source_facet_ids.contains(facet_id))
+        .filter(|facet_id| {
+            all_facets
+                .iter()
+                .any(|f| f.id == *facet_id && f.facet_type == FACET_TYPE_CATEGORICAL)
+        })
+        .collect();
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({
+            "sources": sources.len(),
+            "targets": targets.len(),
+            "source_facet_values": source_facets.len(),
+            "target_facet_values": target_facets.len(),
+        })),
+    );
 
     let nonsignificant_facet_value: DbID = all_facet_values
         .iter()
         .find(|fv| fv.facet_id == dir_facet.id && fv.value == "Non-significant")
-        .unwrap()
+        .ok_or_else(|| {
+            BuildError::Data("Direction facet value \"Non-significant\" is missing".to_string())
+        })?
         .id;
 
+    // Lift every source and target into the output assembly before anything is bucketed. A
+    // feature that fails to lift takes its REOs with it.
+    let mut unlifted_features: Vec<UnliftedFeature> = Vec::new();
+    let mut unlifted_reos: FxHashSet<DbID> = FxHashSet::default();
+    if let Some(liftover) = &liftover {
+        let phase = logger.phase("liftover");
+        // feature id -> lifted location, or None if it couldn't be lifted
+        let mut lifted_features: FxHashMap<DbID, Option<LiftedLocation>> = FxHashMap::default();
+        let features = source_dict
+            .values()
+            .flatten()
+            .map(|source| ("source", source.0, source.2, source.3))
+            .chain(
+                target_dict
+                    .values()
+                    .flatten()
+                    .map(|target| ("target", target.0, target.1, target.2)),
+            );
+        for (kind, feature_id, chrom_name, location) in features {
+            if lifted_features.contains_key(&feature_id) {
+                continue;
+            }
+            let lifted = match GenomicInterval::from_location(location.as_ref()) {
+                // Invalid locations are left for the --out-of-bounds policy to deal with
+                Err(_) => Some((chrom_name, location, false)),
+                Ok(interval) => match liftover.lift(chrom_name, interval) {
+                    Ok(lifted) => {
+                        if lifted.chrom_name != chrom_name {
+                            stats.features_lifted_to_other_chromosomes += 1;
+                        }
+                        Some((lifted.chrom_name, Some(lifted.range()), lifted.reversed))
+                    }
+                    Err(reason) => {
+                        logger.debug(
+                            "Unable to lift feature",
+                            json!({ "kind": kind, "id": feature_id, "reason": reason }),
+                        );
+                        *stats.unlifted_features.entry(reason).or_insert(0) += 1;
+                        unlifted_features.push(UnliftedFeature {
+                            kind,
+                            id: feature_id,
+                            chrom_name: chrom_name.to_string(),
+                            interval,
+                            reason,
+                        });
+                        None
+                    }
+                },
+            };
+            lifted_features.insert(feature_id, lifted);
+        }
+
+        for (reo_id, re_sources) in source_dict.iter_mut() {
+            for source in re_sources.iter_mut() {
+                match lifted_features[&source.0] {
+                    Some((chrom_name, location, _)) => {
+                        source.2 = chrom_name;
+                        source.3 = location;
+                    }
+                    None => {
+                        unlifted_reos.insert(*reo_id);
+                    }
+                }
+            }
+        }
+        for (reo_id, re_targets) in target_dict.iter_mut() {
+            for target in re_targets.iter_mut() {
+                match lifted_features[&target.0] {
+                    Some((chrom_name, location, reversed)) => {
+                        target.1 = chrom_name;
+                        target.2 = location;
+                        if reversed {
+                            target.3 = match target.3 {
+                                "+" => "-",
+                                "-" => "+",
+                                strand => strand,
+                            };
+                        }
+                    }
+                    None => {
+                        unlifted_reos.insert(*reo_id);
+                    }
+                }
+            }
+        }
+        if !unlifted_features.is_empty() {
+            logger.warn(
+                "Some features failed to lift over",
+                json!({ "count": unlifted_features.len() }),
+            );
+        }
+        // REOs were selected by their input assembly chromosome, so REOs with features that
+        // lifted onto this chromosome from another one aren't in a chromosome build
+        if options.chromo.is_some() && stats.features_lifted_to_other_chromosomes > 0 {
+            logger.warn(
+                "Some features lifted to another chromosome",
+                json!({ "count": stats.features_lifted_to_other_chromosomes }),
+            );
+        }
+        stats.timings_ms.insert(
+            phase.name(),
+            phase.finish(json!({ "lifted": lifted_features.len() - unlifted_features.len() })),
+        );
+    }
+
+    // Sequences outside the primary assembly, and how far into them the features go
+    let mut contigs: BTreeMap<String, i32> = BTreeMap::new();
+    if options.contig_policy != ContigPolicy::Exclude {
+        let locations = source_dict
+            .values()
+            .flatten()
+            .map(|source| (source.2, &source.3))
+            .chain(
+                target_dict
+                    .values()
+                    .flatten()
+                    .map(|target| (target.1, &target.2)),
+            );
+        for (chrom_name, location) in locations {
+            let primary = chrom_name
+                .strip_prefix("chr")
+                .and_then(|name| chrom_keys.get(name))
+                .is_some();
+            if primary {
+                continue;
+            }
+            if let Ok(interval) = GenomicInterval::from_location(location.as_ref()) {
+                let extent = contigs.entry(chrom_name.to_string()).or_insert(0);
+                *extent = (*extent).max(interval.half_open().1);
+            }
+        }
+        logger.info(
+            "Found non-primary contigs",
+            json!({ "count": contigs.len() }),
+        );
+    }
+    let chromosomes = Chromosomes::new(&assembly_info, &contigs, options.contig_policy)
+        .map_err(BuildError::Data)?;
+    let chrom_data: Vec<ChromosomeData> = chromosomes
+        .names
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| ChromosomeData::from(name, idx as u8))
+        .collect();
+
     // For each regulatory effect we want to add all the facets associated with the effect itself,
     // its sources and its targets to the bucket associated with the each source and target.
     // For each source we want to keep track of all the target buckets it's associated with, and for each
     // source we want to keep track of all the source buckets it's associated with.
+    // Regions are in output assembly coordinates, so they're checked against the lifted
+    // locations
+    let region_index = if options.regions.is_empty() {
+        None
+    } else {
+        logger.info(
+            "Restricting the build to regions",
+            json!({ "regions": options.regions.len() }),
+        );
+        Some(RegionIndex::new(&options.regions))
+    };
+
+    let phase = logger.phase("bucketing");
+    let mut progress = logger.progress("bucketing", reg_effect_id_list.len() as u64);
     for reo_id in reg_effect_id_list {
-        let re_facets = reg_effect_num_facets.get(&(reo_id as DbID)).unwrap();
-        let effect_size = *re_facets.get(FACET_EFFECT_SIZE).unwrap();
-        let significance: f64 = (*re_facets.get(FACET_SIGNIFICANCE).unwrap()).into();
+        progress.tick();
+        let re_facets = reg_effect_num_facets
+            .get(&(reo_id as DbID))
+            .ok_or_else(|| BuildError::Data(format!("REO {} has no numeric facets", reo_id)))?;
+        let missing_facet =
+            |name: &str| BuildError::Data(format!("REO {} has no {} value", reo_id, name));
+        let effect_size = *re_facets
+            .get(FACET_EFFECT_SIZE)
+            .ok_or_else(|| missing_facet(FACET_EFFECT_SIZE))?;
+        let significance: f64 = (*re_facets
+            .get(FACET_SIGNIFICANCE)
+            .ok_or_else(|| missing_facet(FACET_SIGNIFICANCE))?)
+        .into();
+
+        let re_sources = source_dict
+            .get(&(reo_id as DbID))
+            .ok_or_else(|| BuildError::Data(format!("REO {} has no sources", reo_id)))?;
+
+        if unlifted_reos.contains(&(reo_id as DbID)) {
+            stats.drop_reo(DROPPED_UNLIFTED, re_sources.len());
+            continue;
+        }
+
+        // Region builds keep an REO if any of its sources or its target is in a region. This
+        // is checked before the bounds so features outside the regions can't fail the build.
+        if let Some(region_index) = &region_index {
+            let in_region = |chrom_name: &str, location: &Option<Range<i32>>| {
+                GenomicInterval::from_location(location.as_ref())
+                    .map(|interval| region_index.overlaps(chrom_name, &interval))
+                    .unwrap_or(false)
+            };
+            let sources_in_region = re_sources
+                .iter()
+                .any(|source| in_region(source.2, &source.3));
+            let target_in_region = target_dict
+                .get(&(reo_id as DbID))
+                .map(|targets| in_region(targets[0].1, &targets[0].2))
+                .unwrap_or(false);
+            if !sources_in_region && !target_in_region {
+                stats.reos_outside_regions += 1;
+                continue;
+            }
+        }
+
+        // Check the sources' and target's coordinates before recording anything from this REO,
+        // so a dropped REO doesn't leave its features behind
+        let mut source_locations = Vec::with_capacity(re_sources.len());
+        for source in re_sources {
+            // Only --contigs=exclude leaves sequences out of the chromosomes
+            let placement = match chromosomes.placement(source.2) {
+                Some(placement) => placement,
+                None => {
+                    logger.debug(
+                        "Skipping REO with a source on an unknown chromosome",
+                        json!({ "reo_id": reo_id, "chrom": source.2 }),
+                    );
+                    stats.drop_reo(DROPPED_UNKNOWN_SOURCE_CHROMOSOME, re_sources.len());
+                    break;
+                }
+            };
+            match bounded_location(
+                "source",
+                source.0,
+                source.3.as_ref(),
+                placement,
+                options,
+                logger,
+                &mut stats,
+            )? {
+                Ok((start, end)) => source_locations.push((placement, start, end)),
+                Err(reason) => {
+                    stats.drop_reo(reason, re_sources.len());
+                    break;
+                }
+            }
+        }
+        if source_locations.len() < re_sources.len() {
+            continue;
+        }
+
+        let target = match target_dict.get(&(reo_id as DbID)) {
+            Some(targets) => {
+                let target = targets[0];
+                let placement = match chromosomes.placement(target.1) {
+                    Some(placement) => placement,
+                    None => {
+                        logger.debug(
+                            "Skipping REO with a target on an unknown chromosome",
+                            json!({ "reo_id": reo_id, "chrom": target.1 }),
+                        );
+                        stats.drop_reo(DROPPED_UNKNOWN_TARGET_CHROMOSOME, re_sources.len());
+                        continue;
+                    }
+                };
+                match bounded_location(
+                    "target",
+                    target.0,
+                    target.2.as_ref(),
+                    placement,
+                    options,
+                    logger,
+                    &mut stats,
+                )? {
+                    Ok((start, end)) => Some((target, placement, start, end)),
+                    Err(reason) => {
+                        stats.drop_reo(reason, re_sources.len());
+                        continue;
+                    }
+                }
+            }
+            None => None,
+        };
 
-        let re_sources = source_dict.get(&(reo_id as DbID)).unwrap();
+        // The target's sequence, as in the database so contigs sharing the "other"
+        // pseudo-chromosome aren't mistaken for one another, and its TSS
+        let target_tss =
+            target.map(|(target, _, start, end)| (target.1, target_tss(target.3, start, end)));
+        // (interaction facet value id, distance) for each source, or None without a target
+        let mut source_links: Vec<Option<(DbID, Option<u32>)>> =
+            Vec::with_capacity(re_sources.len());
 
         let mut source_counter: FxHashSet<BucketLoc> = FxHashSet::default();
 
         let mut source_cat_facets: FxHashSet<DbID> = FxHashSet::default();
+        let mut target_cat_facets: FxHashSet<DbID> = FxHashSet::default();
         let mut reg_cat_facets: FxHashSet<DbID> = FxHashSet::default();
 
         // The only categorical REO facet we care about is the direction (depleted, enriched, or non-significant)
@@ -341,8 +1093,12 @@ pub fn build_data(
                 .filter(|f| f.2 == dir_facet.id)
                 .for_each(|f| drop(reg_cat_facets.insert(f.0)));
         }
+        if let Some(value_id) = reg_effect_analysis.get(&(reo_id as DbID)) {
+            reg_cat_facets.insert(*value_id);
+        }
 
-        for source in re_sources {
+        for (source, (placement, start, end)) in re_sources.iter().zip(source_locations) {
+            let chrom = placement.chrom;
             for source_facets in &source_facet_dict.get(&source.0) {
                 source_facets
                     .iter()
@@ -350,75 +1106,200 @@ pub fn build_data(
                     .for_each(|f| drop(source_cat_facets.insert(f.0)));
             }
 
+            source_links.push(target_tss.map(|tss| {
+                match cis_distance(source.2, start, end, tss) {
+                    Some(distance) => (cis_value, Some(distance)),
+                    None => (trans_value, None),
+                }
+            }));
+
             let bucket_loc = BucketLoc {
-                chrom: *chrom_keys
-                    .get(source.2.strip_prefix("chr").unwrap())
-                    .unwrap(),
-                idx: bucket(source.3.lower().unwrap().value as u32),
+                chrom,
+                idx: bucket(start),
             };
             source_counter.insert(bucket_loc);
             feature_buckets.insert(source.0, bucket_loc);
             source_set.insert(source.0);
+            feature_info.insert(
+                source.0,
+                FeatureInfo {
+                    sequence: placement.sequence,
+                    start: start - placement.offset as u32,
+                    end: end - placement.offset as u32,
+                    strand: None,
+                    facet_value_ids: source_facet_dict
+                        .get(&source.0)
+                        .map(|facets| {
+                            facets
+                                .iter()
+                                .filter(|f| source_facet_ids.contains(&f.2))
+                                .map(|f| f.0)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                },
+            );
         }
 
-        let cat_facets = &reg_cat_facets | &source_cat_facets;
-
         let mut target_id: Option<DbID> = None;
-        if let Some(targets) = target_dict.get(&(reo_id as DbID)) {
-            let target = targets[0];
+        if let Some((target, placement, start, end)) = target {
+            let chrom = placement.chrom;
             target_id = Some(target.0);
-            let chrom_name = target.1.strip_prefix("chr").unwrap();
-            let x = chrom_keys.get(chrom_name);
-            if let None = x {
-                continue;
+            if let Some(target_facets) = target_facet_dict.get(&target.0) {
+                target_cat_facets.extend(
+                    target_facets
+                        .iter()
+                        .filter(|f| target_facet_ids.contains(&f.2))
+                        .map(|f| f.0),
+                );
             }
             let target_start = match target.3 {
-                "-" => target.2.upper().unwrap().value,
-                _ => target.2.lower().unwrap().value,
+                "-" => end - 1,
+                _ => start,
             };
-            let target_bucket = bucket(target_start as u32);
             let target_bucket = BucketLoc {
-                chrom: *chrom_keys.get(chrom_name).unwrap(),
-                idx: target_bucket,
+                chrom,
+                idx: bucket(target_start),
             };
             feature_buckets.insert(target.0, target_bucket);
             target_set.insert(target.0);
+            feature_info.insert(
+                target.0,
+                FeatureInfo {
+                    sequence: placement.sequence,
+                    start: start - placement.offset as u32,
+                    end: end - placement.offset as u32,
+                    strand: target.3.chars().next(),
+                    facet_value_ids: target_facet_dict
+                        .get(&target.0)
+                        .map(|facets| {
+                            facets
+                                .iter()
+                                .filter(|f| target_facet_ids.contains(&f.2))
+                                .map(|f| f.0)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                },
+            );
         }
 
-        if reg_cat_facets.contains(&nonsignificant_facet_value) {
-            for (sid, _, _, _) in re_sources {
-                nonsignificant_observations.push(ObservationData {
-                    reo_id: reo_id as DbID,
-                    facet_value_ids: cat_facets.iter().cloned().collect(),
-                    source_id: *sid,
-                    target_id,
-                    effect_size,
-                    significance,
-                    neg_log_significance: -significance.max(MIN_SIG).log10(),
-                });
-            }
+        reg_cat_facets.insert(match target_id {
+            Some(_) => has_target_value,
+            None => no_target_value,
+        });
+
+        let cat_facets = &(&reg_cat_facets | &source_cat_facets) | &target_cat_facets;
+
+        let observations = if reg_cat_facets.contains(&nonsignificant_facet_value) {
+            &mut nonsignificant_observations
         } else {
-            for (sid, _, _, _) in re_sources {
-                significant_observations.push(ObservationData {
-                    reo_id: reo_id as DbID,
-                    facet_value_ids: cat_facets.iter().cloned().collect(),
-                    source_id: *sid,
-                    target_id,
-                    effect_size,
-                    significance,
-                    neg_log_significance: -significance.max(MIN_SIG).log10(),
-                });
+            &mut significant_observations
+        };
+        // Each source is its own observation, so each can be cis or trans
+        for ((sid, _, _, _), link) in re_sources.iter().zip(source_links) {
+            let mut facet_value_ids: Vec<DbID> = cat_facets.iter().cloned().collect();
+            if let Some((interaction_value, distance)) = link {
+                facet_value_ids.push(interaction_value);
+                facet_ids.insert(interaction_value);
+                if let Some(distance) = distance {
+                    let bin = distance_bin(distance);
+                    facet_value_ids.push(distance_value_ids[bin]);
+                    facet_ids.insert(distance_value_ids[bin]);
+                    observation_distances.insert((reo_id as DbID, *sid), distance);
+                }
             }
+            observations.push(ObservationData {
+                reo_id: reo_id as DbID,
+                facet_value_ids,
+                source_id: *sid,
+                target_id,
+                effect_size,
+                significance,
+                neg_log_significance: -significance.max(MIN_SIG).log10(),
+            });
         }
 
         facet_ids.extend(&cat_facets);
     }
 
-    println!(
-        "Buckets filled... {:.0}s",
-        re_start_time.elapsed().as_secs()
+    for (reason, count) in &stats.dropped_reos {
+        logger.warn(
+            "Dropped REOs",
+            json!({
+                "reason": reason,
+                "count": count,
+                "observations": stats.dropped_observations[reason],
+            }),
+        );
+    }
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({
+            "significant_observations": significant_observations.len(),
+            "nonsignificant_observations": nonsignificant_observations.len(),
+        })),
+    );
+
+    stats.reo_count = reg_effects.len() as u64;
+    stats.significant_observations = significant_observations.len() as u64;
+    stats.nonsignificant_observations = nonsignificant_observations.len() as u64;
+    stats.unique_sources = source_set.len();
+    stats.unique_targets = target_set.len();
+    for observation in significant_observations
+        .iter()
+        .chain(nonsignificant_observations.iter())
+    {
+        if let Some(bucket) = feature_buckets.get(&observation.source_id) {
+            stats
+                .observations_per_chromosome
+                .entry(chromosomes.full_names[bucket.chrom as usize].clone())
+                .or_default()
+                .source_observations += 1;
+        }
+        if let Some(bucket) = observation
+            .target_id
+            .and_then(|target_id| feature_buckets.get(&target_id))
+        {
+            stats
+                .observations_per_chromosome
+                .entry(chromosomes.full_names[bucket.chrom as usize].clone())
+                .or_default()
+                .target_observations += 1;
+        }
+    }
+
+    let phase = logger.phase("summaries");
+    let direction_value_ids: FxHashSet<DbID> = all_facet_values
+        .iter()
+        .filter(|fv| fv.facet_id == dir_facet.id)
+        .map(|fv| fv.id)
+        .collect();
+    let summary = summarize(
+        significant_observations
+            .iter()
+            .chain(nonsignificant_observations.iter()),
+        &feature_buckets,
+        &direction_value_ids,
+        options.bucket_size,
+    );
+    let interactions = interaction_matrix(
+        significant_observations
+            .iter()
+            .chain(nonsignificant_observations.iter()),
+        &feature_buckets,
+        options.bucket_size,
+    );
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({
+            "source_buckets": summary.source_buckets.len(),
+            "target_buckets": summary.target_buckets.len(),
+            "interactions": interactions.interactions.len(),
+        })),
     );
 
+    let phase = logger.phase("facet assembly");
     // These are all the facets that are potentially relevant for coverage filtering
     let experiment_facet_coverages = facet_set();
     let experiment_facet_names: FxHashSet<&str> = FxHashSet::from_iter(
@@ -433,19 +1314,47 @@ pub fn build_data(
         .into_iter(),
     );
 
+    let value_counts = facet_value_counts(
+        significant_observations
+            .iter()
+            .chain(nonsignificant_observations.iter()),
+    );
+    let effect_sizes: Vec<f64> = significant_observations
+        .iter()
+        .chain(nonsignificant_observations.iter())
+        .map(|o| o.effect_size as f64)
+        .collect();
+    let significances: Vec<f64> = significant_observations
+        .iter()
+        .chain(nonsignificant_observations.iter())
+        .map(|o| o.significance)
+        .collect();
+    // Raw significance values pile up next to zero, so the histogram is binned on the
+    // -log10 scale the viewer already uses for plotting.
+    let neg_log_significances: Vec<f64> = significant_observations
+        .iter()
+        .chain(nonsignificant_observations.iter())
+        .map(|o| o.neg_log_significance)
+        .collect();
+
     // The idea is to filter out facets that are in the database, but aren't used to annotate
     // data for this particular experiment.
     let mut facets = Vec::<&Facet>::new();
-    for facet in all_facets
-        .iter_mut()
-        .filter(|f| experiment_facet_names.contains(f.name.as_str()))
-    {
-        facet.coverage = Some(
-            experiment_facet_coverages
-                .get(facet.name.as_str())
-                .unwrap()
-                .clone(),
-        );
+    let mut facet_stats = Vec::<FacetStats>::new();
+    for facet in all_facets.iter_mut().filter(|f| {
+        experiment_facet_names.contains(f.name.as_str())
+            || target_facet_ids.contains(&f.id)
+            || synthetic_facet_ids.contains(&f.id)
+    }) {
+        facet.coverage = Some(match experiment_facet_coverages.get(facet.name.as_str()) {
+            Some(coverage) => coverage.clone(),
+            // Synthetic facets describe the whole observation, like the direction does
+            None if synthetic_facet_ids.contains(&facet.id) => experiment_facet_coverages
+                .get(FACET_DIRECTION)
+                .cloned()
+                .unwrap_or_default(),
+            None => FxHashSet::from_iter([FacetCoverage::Target]),
+        });
         if facet.facet_type == FACET_TYPE_CATEGORICAL {
             let facet_values: FxHashMap<DbID, String> = all_facet_values
                 .iter()
@@ -455,43 +1364,163 @@ pub fn build_data(
             if facet_values.len() == 0 {
                 continue;
             }
+            facet_stats.push(FacetStats {
+                facet_id: facet.id,
+                value_counts: Some(
+                    facet_values
+                        .keys()
+                        .map(|id| (*id, *value_counts.get(id).unwrap_or(&0)))
+                        .collect(),
+                ),
+                histogram: None,
+                global_range: None,
+            });
+            stats.facet_value_coverage.insert(
+                facet.name.clone(),
+                FacetValueCoverage {
+                    values_defined: all_facet_values
+                        .iter()
+                        .filter(|f| f.facet_id == facet.id)
+                        .count(),
+                    values_observed: facet_values.len(),
+                    observations: facet_values
+                        .iter()
+                        .map(|(id, value)| (value.clone(), *value_counts.get(id).unwrap_or(&0)))
+                        .collect(),
+                },
+            );
             facet.values = Some(facet_values);
         } else if facet.name == FACET_EFFECT_SIZE {
-            let facet_range_row = client.query_one(
-                &facet_range_statement,
-                &[&FACET_EFFECT_SIZE, &options.analysis_accession_id],
-            )?;
-            facet.range = Some(FacetRange(
-                facet_range_row.get::<&str, f64>("min") as f32,
-                facet_range_row.get::<&str, f64>("max") as f32,
-            ));
+            facet.range =
+                value_range(&effect_sizes).map(|(min, max)| FacetRange(min as f32, max as f32));
+            let global_range = if options.global_ranges {
+                let facet_range_row = client.query_one(
+                    &facet_range_statement,
+                    &[&FACET_EFFECT_SIZE, &options.analysis_accession_ids],
+                )?;
+                Some((
+                    facet_range_row.get::<&str, f64>("min"),
+                    facet_range_row.get::<&str, f64>("max"),
+                ))
+            } else {
+                None
+            };
+            facet_stats.push(FacetStats {
+                facet_id: facet.id,
+                value_counts: None,
+                histogram: FacetHistogram::from_values(
+                    &effect_sizes,
+                    HISTOGRAM_BIN_COUNT,
+                    HistogramScale::Linear,
+                ),
+                global_range,
+            });
         } else if facet.name == FACET_SIGNIFICANCE {
-            let facet_range_row = client.query_one(
-                &facet_range_statement,
-                &[&FACET_SIGNIFICANCE, &options.analysis_accession_id],
-            )?;
-            facet.range64 = Some(FacetRange64(
-                facet_range_row.get::<&str, f64>("min"),
-                facet_range_row.get::<&str, f64>("max"),
-            ));
+            facet.range64 = value_range(&significances).map(|(min, max)| FacetRange64(min, max));
+            let global_range = if options.global_ranges {
+                let facet_range_row = client.query_one(
+                    &facet_range_statement,
+                    &[&FACET_SIGNIFICANCE, &options.analysis_accession_ids],
+                )?;
+                Some((
+                    facet_range_row.get::<&str, f64>("min"),
+                    facet_range_row.get::<&str, f64>("max"),
+                ))
+            } else {
+                None
+            };
+            facet_stats.push(FacetStats {
+                facet_id: facet.id,
+                value_counts: None,
+                histogram: FacetHistogram::from_values(
+                    &neg_log_significances,
+                    HISTOGRAM_BIN_COUNT,
+                    HistogramScale::NegLog10,
+                ),
+                global_range,
+            });
         }
 
         facets.push(facet);
     }
+    stats.timings_ms.insert(
+        phase.name(),
+        phase.finish(json!({ "facets": facets.len() })),
+    );
 
-    Ok((
-        CoverageData {
+    Ok(BuildOutput {
+        coverage: CoverageData {
             significant_observations,
             nonsignificant_observations,
             bucket_size: options.bucket_size,
             chromosomes: chrom_data,
             facets: facets.into_iter().cloned().collect(),
-            chrom_lengths: assembly_info.iter().map(|c| c.1 as usize).collect(),
+            chrom_lengths: chromosomes.lengths.iter().map(|l| *l as usize).collect(),
             feature_buckets,
         },
-        ExperimentFeatureData {
+        features: ExperimentFeatureData {
             sources: source_set,
             targets: target_set,
         },
-    ))
+        facet_stats: ExperimentFacetStats {
+            facets: facet_stats,
+        },
+        summary,
+        interactions,
+        feature_info,
+        observation_distances,
+        chrom_names: chromosomes.full_names,
+        sequences: chromosomes.sequences,
+        stats,
+        unlifted_features,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tss_by_strand() {
+        assert_eq!(target_tss("+", 100, 200), 100);
+        assert_eq!(target_tss(".", 100, 200), 100);
+        assert_eq!(target_tss("-", 100, 200), 199);
+    }
+
+    #[test]
+    fn cis_and_trans() {
+        // The source's middle is 150
+        assert_eq!(cis_distance("chr1", 100, 200, ("chr1", 1150)), Some(1000));
+        assert_eq!(cis_distance("chr1", 100, 200, ("chr1", 50)), Some(100));
+        assert_eq!(cis_distance("chr1", 100, 200, ("chr1", 150)), Some(0));
+        assert_eq!(cis_distance("chr1", 100, 200, ("chr2", 150)), None);
+        // Contigs sharing the "other" pseudo-chromosome are still different sequences
+        assert_eq!(
+            cis_distance(
+                "chrUn_KI270742v1",
+                100,
+                200,
+                ("chr1_KI270706v1_random", 150)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn distance_bins() {
+        for (distance, bin) in [
+            (0, 0),
+            (999, 0),
+            (1_000, 1),
+            (9_999, 1),
+            (10_000, 2),
+            (99_999, 2),
+            (100_000, 3),
+            (999_999, 3),
+            (1_000_000, 4),
+            (u32::MAX, 4),
+        ] {
+            assert_eq!(distance_bin(distance), bin, "distance {}", distance);
+        }
+    }
 }
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+// Reasons an REO can be left out of the build without failing it
+pub const DROPPED_UNKNOWN_SOURCE_CHROMOSOME: &str = "source on unknown chromosome";
+pub const DROPPED_UNKNOWN_TARGET_CHROMOSOME: &str = "target on unknown chromosome";
+pub const DROPPED_OUT_OF_BOUNDS: &str = "coordinates outside chromosome";
+pub const DROPPED_INVALID_LOCATION: &str = "null, empty, or unbounded location";
+pub const DROPPED_UNLIFTED: &str = "source or target failed to lift over";
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChromosomeCounts {
+    // Observations whose source is on the chromosome
+    pub source_observations: u64,
+    // Observations whose target is on the chromosome
+    pub target_observations: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FacetValueCoverage {
+    // Number of values the facet has in the database
+    pub values_defined: usize,
+    // Number of those values used by at least one observation
+    pub values_observed: usize,
+    // value -> number of observations with that value
+    pub observations: BTreeMap<String, u64>,
+}
+
+// Written as <name>_stats.json next to the other outputs so the counts can be checked without
+// going back to the database.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BuildStats {
+    pub reo_count: u64,
+    pub significant_observations: u64,
+    pub nonsignificant_observations: u64,
+    pub unique_sources: u64,
+    pub unique_targets: u64,
+    // chromosome name -> counts
+    pub observations_per_chromosome: BTreeMap<String, ChromosomeCounts>,
+    // reason -> number of REOs left out of the build
+    pub dropped_reos: BTreeMap<&'static str, u64>,
+    // reason -> number of observations those REOs would have had (one per source)
+    pub dropped_observations: BTreeMap<&'static str, u64>,
+    // REOs left out because neither their sources nor their target are in the --region(s)
+    pub reos_outside_regions: u64,
+    // Sources and targets whose coordinates were clipped to their chromosome
+    pub clipped_features: u64,
+    // reason -> number of sources and targets that failed to lift over
+    pub unlifted_features: BTreeMap<&'static str, u64>,
+    // Sources and targets that lifted to a different chromosome than the one they're on in the
+    // input assembly
+    pub features_lifted_to_other_chromosomes: u64,
+    // categorical facet name -> coverage
+    pub facet_value_coverage: BTreeMap<String, FacetValueCoverage>,
+    // phase -> milliseconds
+    pub timings_ms: BTreeMap<&'static str, f64>,
+}
+
+impl BuildStats {
+    pub fn drop_reo(&mut self, reason: &'static str, observations: usize) {
+        *self.dropped_reos.entry(reason).or_insert(0) += 1;
+        *self.dropped_observations.entry(reason).or_insert(0) += observations as u64;
+    }
+}
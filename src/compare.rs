@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use cov_viz_ds::{BucketLoc, CoverageData, DbID, ObservationData};
+
+use crate::error::BuildError;
+use crate::output::read_output;
+
+fn read_coverage(path: &Path) -> Result<(CoverageData, Option<String>), BuildError> {
+    let file = read_output(path)?;
+    let coverage = bincode::deserialize(&file.payload).map_err(|e| {
+        BuildError::Data(format!(
+            "{} isn't a coverage data (.ecd) file: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    Ok((
+        coverage,
+        file.header
+            .parameters
+            .and_then(|parameters| parameters.chromosome),
+    ))
+}
+
+fn observations(coverage: &CoverageData) -> impl Iterator<Item = &ObservationData> + Clone {
+    coverage
+        .significant_observations
+        .iter()
+        .chain(coverage.nonsignificant_observations.iter())
+}
+
+// The (REO id, source id) pairs of every REO with a source or target bucketed on the chromosome,
+// which is what a chromosome build of the same analysis selects
+fn chromosome_observations<'a>(
+    observations: impl Iterator<Item = &'a ObservationData> + Clone,
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    chrom_index: u8,
+) -> BTreeSet<(DbID, DbID)> {
+    let on_chromosome = |feature_id: DbID| {
+        feature_buckets
+            .get(&feature_id)
+            .map(|bucket| bucket.chrom == chrom_index)
+            .unwrap_or(false)
+    };
+    let selected_reos: FxHashSet<DbID> = observations
+        .clone()
+        .filter(|o| on_chromosome(o.source_id) || o.target_id.map(on_chromosome).unwrap_or(false))
+        .map(|o| o.reo_id)
+        .collect();
+
+    observations
+        .filter(|o| selected_reos.contains(&o.reo_id))
+        .map(|o| (o.reo_id, o.source_id))
+        .collect()
+}
+
+// cov_viz --compare <level1 .ecd file> <level2 .ecd file>
+//
+// Checks that a level2 build has the same observations, as (REO id, source id) pairs, as the
+// REOs of a level1 build with a source or target on the level2 build's chromosome. Prints every
+// pair only one of the builds has and returns the number of them.
+pub fn compare(args: &[String]) -> Result<usize, BuildError> {
+    let (level1_path, level2_path) = match args {
+        [level1_path, level2_path, ..] => (Path::new(level1_path), Path::new(level2_path)),
+        _ => {
+            return Err(BuildError::Config(
+                "Usage: cov_viz --compare <level1 .ecd file> <level2 .ecd file>".to_string(),
+            ))
+        }
+    };
+    let (level1, _) = read_coverage(level1_path)?;
+    let (level2, chromosome) = read_coverage(level2_path)?;
+    let chromosome = chromosome.ok_or_else(|| {
+        BuildError::Config(format!(
+            "{} isn't from a chromosome build",
+            level2_path.display()
+        ))
+    })?;
+
+    // The level1 build's chromosome index, which its feature buckets refer to
+    let chrom_index = chromosome
+        .strip_prefix("chr")
+        .and_then(|name| level1.chromosomes.iter().find(|c| c.chrom == name))
+        .map(|c| c.index)
+        .ok_or_else(|| {
+            BuildError::Data(format!(
+                "{} has no chromosome {}",
+                level1_path.display(),
+                chromosome
+            ))
+        })?;
+    let expected =
+        chromosome_observations(observations(&level1), &level1.feature_buckets, chrom_index);
+    let actual: BTreeSet<(DbID, DbID)> = observations(&level2)
+        .map(|o| (o.reo_id, o.source_id))
+        .collect();
+
+    let mut differences = 0;
+    for (label, pairs) in [
+        ("only in level1", expected.difference(&actual)),
+        ("only in level2", actual.difference(&expected)),
+    ] {
+        for (reo_id, source_id) in pairs {
+            println!("{}: reo {} source {}", label, reo_id, source_id);
+            differences += 1;
+        }
+    }
+    println!(
+        "{} observations on {} in level1, {} in level2, {} differences",
+        expected.len(),
+        chromosome,
+        actual.len(),
+        differences
+    );
+
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(reo_id: DbID, source_id: DbID, target_id: Option<DbID>) -> ObservationData {
+        ObservationData {
+            reo_id,
+            facet_value_ids: Vec::new(),
+            source_id,
+            target_id,
+            effect_size: 0.0,
+            significance: 1.0,
+            neg_log_significance: 0.0,
+        }
+    }
+
+    #[test]
+    fn selects_reos_with_a_feature_on_the_chromosome() {
+        let observations = [
+            // Both sources on chromosome 0
+            observation(1, 10, None),
+            observation(1, 11, None),
+            // One source on chromosome 0 and one on 1, so both are selected
+            observation(2, 12, None),
+            observation(2, 20, None),
+            // Source on chromosome 1, target on 0
+            observation(3, 21, Some(30)),
+            // Source and target on chromosome 1
+            observation(4, 22, Some(31)),
+        ];
+        let feature_buckets: FxHashMap<DbID, BucketLoc> = [
+            (10, 0),
+            (11, 0),
+            (12, 0),
+            (20, 1),
+            (21, 1),
+            (22, 1),
+            (30, 0),
+            (31, 1),
+        ]
+        .into_iter()
+        .map(|(id, chrom)| (id, BucketLoc { chrom, idx: 0 }))
+        .collect();
+
+        assert_eq!(
+            chromosome_observations(observations.iter(), &feature_buckets, 0),
+            BTreeSet::from([(1, 10), (1, 11), (2, 12), (2, 20), (3, 21)])
+        );
+        assert_eq!(
+            chromosome_observations(observations.iter(), &feature_buckets, 1),
+            BTreeSet::from([(2, 12), (2, 20), (3, 21), (4, 22)])
+        );
+        assert!(chromosome_observations(observations.iter(), &feature_buckets, 2).is_empty());
+    }
+}
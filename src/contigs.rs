@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use rustc_hash::FxHashMap;
+
+// Name of the pseudo-chromosome holding every non-primary contig with ContigPolicy::Other
+pub const OTHER_CHROMOSOME: &str = "other";
+
+// What to do with features on sequences that aren't part of the primary assembly (chrUn_*,
+// *_alt, *_random, patches, etc.)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContigPolicy {
+    // Leave them out, like any other unknown chromosome: REOs with a source or target on one
+    // are dropped from the build
+    Exclude,
+    // Add each contig as an extra chromosome after the primary ones
+    Include,
+    // Put all of the contigs end to end in a single "other" pseudo-chromosome
+    Other,
+}
+
+impl FromStr for ContigPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exclude" => Ok(ContigPolicy::Exclude),
+            "include" => Ok(ContigPolicy::Include),
+            "other" => Ok(ContigPolicy::Other),
+            _ => Err(format!(
+                "Invalid contig policy {}. Must be \"exclude\", \"include\", or \"other\"",
+                s
+            )),
+        }
+    }
+}
+
+// Where a sequence's features go in the output: the chromosome, the offset of the sequence
+// within it, and the length of the sequence itself. The offset is only non-zero for contigs
+// in the "other" pseudo-chromosome.
+#[derive(Clone, Copy, Debug)]
+pub struct ContigPlacement {
+    pub chrom: u8,
+    // Index of the sequence in Chromosomes::sequences
+    pub sequence: u32,
+    pub offset: i32,
+    pub length: i32,
+}
+
+// The chromosomes written to the output: the primary assembly, plus any non-primary contigs
+// the contig policy adds.
+pub struct Chromosomes {
+    // Without the "chr" prefix, as in the assembly tables (e.g., "1", "X", "Un_KI270302v1")
+    pub names: Vec<String>,
+    // As they appear in the database (e.g., "chr1"), for the genome browser exports
+    pub full_names: Vec<String>,
+    pub lengths: Vec<i32>,
+    // Every sequence the features can be on, as in the database, with its length. The same as
+    // full_names and lengths, except that the contigs in the "other" pseudo-chromosome are
+    // listed individually.
+    pub sequences: Vec<(String, i32)>,
+    // database chromosome name -> placement
+    placements: FxHashMap<String, ContigPlacement>,
+}
+
+impl Chromosomes {
+    // `contigs` is every non-primary sequence name seen in the data along with the furthest
+    // coordinate of a feature on it, which stands in for the contig's length since the
+    // assembly tables only have the primary sequences.
+    pub fn new(
+        assembly_info: &[(&'static str, i32, u8)],
+        contigs: &BTreeMap<String, i32>,
+        policy: ContigPolicy,
+    ) -> Result<Self, String> {
+        let mut chromosomes = Chromosomes {
+            names: Vec::new(),
+            full_names: Vec::new(),
+            lengths: Vec::new(),
+            sequences: Vec::new(),
+            placements: FxHashMap::default(),
+        };
+        for (name, length, _) in assembly_info {
+            chromosomes.add(name.to_string(), format!("chr{}", name), *length);
+        }
+
+        match policy {
+            ContigPolicy::Exclude => (),
+            ContigPolicy::Include => {
+                if assembly_info.len() + contigs.len() > u8::MAX as usize + 1 {
+                    return Err(format!(
+                        "There are too many non-primary contigs ({}) to include each as a chromosome; use --contigs=other instead",
+                        contigs.len()
+                    ));
+                }
+                for (full_name, length) in contigs {
+                    let name = full_name.strip_prefix("chr").unwrap_or(full_name);
+                    chromosomes.add(name.to_string(), full_name.clone(), *length);
+                }
+            }
+            ContigPolicy::Other => {
+                if !contigs.is_empty() {
+                    let chrom = chromosomes.names.len() as u8;
+                    let mut offset: i32 = 0;
+                    for (full_name, length) in contigs {
+                        chromosomes.placements.insert(
+                            full_name.clone(),
+                            ContigPlacement {
+                                chrom,
+                                sequence: chromosomes.sequences.len() as u32,
+                                offset,
+                                length: *length,
+                            },
+                        );
+                        chromosomes.sequences.push((full_name.clone(), *length));
+                        offset = offset.checked_add(*length).ok_or_else(|| {
+                            "The non-primary contigs are too long to fit in one pseudo-chromosome"
+                                .to_string()
+                        })?;
+                    }
+                    chromosomes.names.push(OTHER_CHROMOSOME.to_string());
+                    chromosomes.full_names.push(OTHER_CHROMOSOME.to_string());
+                    chromosomes.lengths.push(offset);
+                }
+            }
+        }
+
+        Ok(chromosomes)
+    }
+
+    fn add(&mut self, name: String, full_name: String, length: i32) {
+        self.placements.insert(
+            full_name.clone(),
+            ContigPlacement {
+                chrom: self.names.len() as u8,
+                sequence: self.sequences.len() as u32,
+                offset: 0,
+                length,
+            },
+        );
+        self.sequences.push((full_name.clone(), length));
+        self.names.push(name);
+        self.full_names.push(full_name);
+        self.lengths.push(length);
+    }
+
+    pub fn placement(&self, chrom_name: &str) -> Option<ContigPlacement> {
+        self.placements.get(chrom_name).cloned()
+    }
+}
@@ -0,0 +1,224 @@
+use std::str::FromStr;
+
+use postgres_range::{BoundType, Range};
+
+// A location on a chromosome as a 0-based, closed interval: `start` and `end` are the first
+// and last bases of the feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenomicInterval {
+    pub start: i32,
+    pub end: i32,
+}
+
+impl GenomicInterval {
+    // Converts a Postgres int4range, which may have inclusive or exclusive bounds (Postgres
+    // normalizes int4ranges to [lower, upper), but nothing stops a range from being built by
+    // hand). Empty and unbounded ranges don't describe a location, so they're errors.
+    pub fn from_range(range: &Range<i32>) -> Result<Self, String> {
+        if range.is_empty() {
+            return Err("location is an empty range".to_string());
+        }
+        let start = match range.lower() {
+            Some(lower) => match lower.type_ {
+                BoundType::Inclusive => lower.value,
+                BoundType::Exclusive => lower.value.saturating_add(1),
+            },
+            None => return Err("location has no lower bound".to_string()),
+        };
+        let end = match range.upper() {
+            Some(upper) => match upper.type_ {
+                BoundType::Inclusive => upper.value,
+                BoundType::Exclusive => upper.value.saturating_sub(1),
+            },
+            None => return Err("location has no upper bound".to_string()),
+        };
+        if start > end {
+            return Err(format!("location {}-{} contains no bases", start, end));
+        }
+
+        Ok(GenomicInterval { start, end })
+    }
+
+    // Converts a feature's location column, which can be NULL. A NULL location describes no
+    // more of a location than an empty range does, so it's an error too.
+    pub fn from_location(location: Option<&Range<i32>>) -> Result<Self, String> {
+        match location {
+            Some(range) => GenomicInterval::from_range(range),
+            None => Err("location is null".to_string()),
+        }
+    }
+
+    // 0-based, half-open, as used in BED files
+    pub fn half_open(&self) -> (i32, i32) {
+        (self.start, self.end.saturating_add(1))
+    }
+}
+
+// What to do with a feature whose coordinates are negative or run past the end of its
+// chromosome
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoundsPolicy {
+    // Clamp the coordinates to the chromosome
+    Clip,
+    // Leave the feature's REO out of the build
+    Drop,
+    // Fail the build
+    Fail,
+}
+
+impl FromStr for BoundsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clip" => Ok(BoundsPolicy::Clip),
+            "drop" => Ok(BoundsPolicy::Drop),
+            "fail" => Ok(BoundsPolicy::Fail),
+            _ => Err(format!(
+                "Invalid out of bounds policy {}. Must be \"clip\", \"drop\", or \"fail\"",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BoundsCheck {
+    InBounds(u32, u32),
+    Clipped(u32, u32),
+    Dropped,
+}
+
+// Checks [start, end) against [0, chrom_length). A feature entirely outside the chromosome
+// has nothing left to clip to, so it's dropped even under BoundsPolicy::Clip.
+pub fn check_bounds(
+    start: i32,
+    end: i32,
+    chrom_length: i32,
+    policy: BoundsPolicy,
+) -> Result<BoundsCheck, String> {
+    if start >= 0 && end <= chrom_length {
+        return Ok(BoundsCheck::InBounds(start as u32, end as u32));
+    }
+
+    match policy {
+        BoundsPolicy::Fail => Err(format!(
+            "coordinates {}-{} are outside the chromosome (length {})",
+            start, end, chrom_length
+        )),
+        BoundsPolicy::Drop => Ok(BoundsCheck::Dropped),
+        BoundsPolicy::Clip => {
+            let start = start.max(0);
+            let end = end.min(chrom_length);
+            if start >= end {
+                Ok(BoundsCheck::Dropped)
+            } else {
+                Ok(BoundsCheck::Clipped(start as u32, end as u32))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use postgres_range::RangeBound;
+
+    fn range(lower: Option<(i32, BoundType)>, upper: Option<(i32, BoundType)>) -> Range<i32> {
+        Range::new(
+            lower.map(|(value, type_)| RangeBound::new(value, type_)),
+            upper.map(|(value, type_)| RangeBound::new(value, type_)),
+        )
+    }
+
+    #[test]
+    fn from_range_bounds() {
+        use BoundType::{Exclusive, Inclusive};
+
+        let expected = GenomicInterval { start: 10, end: 19 };
+        for (lower, upper) in [
+            ((10, Inclusive), (20, Exclusive)),
+            ((10, Inclusive), (19, Inclusive)),
+            ((9, Exclusive), (20, Exclusive)),
+            ((9, Exclusive), (19, Inclusive)),
+        ] {
+            assert_eq!(
+                GenomicInterval::from_range(&range(Some(lower), Some(upper))),
+                Ok(expected)
+            );
+        }
+        assert_eq!(expected.half_open(), (10, 20));
+
+        // A single base
+        assert_eq!(
+            GenomicInterval::from_range(&range(Some((10, Inclusive)), Some((11, Exclusive)))),
+            Ok(GenomicInterval { start: 10, end: 10 })
+        );
+    }
+
+    #[test]
+    fn from_range_without_bases() {
+        use BoundType::{Exclusive, Inclusive};
+
+        assert!(GenomicInterval::from_range(&Range::empty()).is_err());
+        assert!(
+            GenomicInterval::from_range(&range(Some((10, Exclusive)), Some((11, Exclusive))))
+                .is_err()
+        );
+        assert!(GenomicInterval::from_range(&range(None, Some((20, Exclusive)))).is_err());
+        assert!(GenomicInterval::from_range(&range(Some((10, Inclusive)), None)).is_err());
+        assert!(GenomicInterval::from_range(&range(None, None)).is_err());
+        assert!(GenomicInterval::from_location(None).is_err());
+    }
+
+    #[test]
+    fn in_bounds() {
+        for policy in [BoundsPolicy::Clip, BoundsPolicy::Drop, BoundsPolicy::Fail] {
+            assert_eq!(
+                check_bounds(0, 10, 100, policy),
+                Ok(BoundsCheck::InBounds(0, 10))
+            );
+            // Ending on the chromosome's last base
+            assert_eq!(
+                check_bounds(90, 100, 100, policy),
+                Ok(BoundsCheck::InBounds(90, 100))
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        assert_eq!(
+            check_bounds(90, 101, 100, BoundsPolicy::Clip),
+            Ok(BoundsCheck::Clipped(90, 100))
+        );
+        assert_eq!(
+            check_bounds(-5, 10, 100, BoundsPolicy::Clip),
+            Ok(BoundsCheck::Clipped(0, 10))
+        );
+        assert_eq!(
+            check_bounds(90, 101, 100, BoundsPolicy::Drop),
+            Ok(BoundsCheck::Dropped)
+        );
+        assert!(check_bounds(90, 101, 100, BoundsPolicy::Fail).is_err());
+        assert!(check_bounds(-5, 10, 100, BoundsPolicy::Fail).is_err());
+    }
+
+    #[test]
+    fn outside_chromosome() {
+        // Nothing is left to clip to
+        assert_eq!(
+            check_bounds(100, 110, 100, BoundsPolicy::Clip),
+            Ok(BoundsCheck::Dropped)
+        );
+        assert_eq!(
+            check_bounds(-10, 0, 100, BoundsPolicy::Clip),
+            Ok(BoundsCheck::Dropped)
+        );
+        assert_eq!(
+            check_bounds(100, 110, 100, BoundsPolicy::Drop),
+            Ok(BoundsCheck::Dropped)
+        );
+        assert!(check_bounds(100, 110, 100, BoundsPolicy::Fail).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use postgres::Client;
+use serde::Serialize;
+use serde_json::json;
+
+use cov_viz_ds::facets::FACET_DIRECTION;
+
+use crate::build_data::{REO_IN_REGIONS, REO_ON_CHROMOSOMES, REQUIRED_FACETS};
+use crate::error::BuildError;
+use crate::logging::Logger;
+use crate::options::Options;
+use crate::regions::region_columns;
+
+// Rough uncompressed sizes used for the output size estimate: an observation in the .ecd file
+// (ids, effect size, significance, and a handful of facet value ids), and a feature's bucket
+// plus its entry in the .fd bitmaps.
+const ESTIMATED_OBSERVATION_BYTES: u64 = 80;
+const ESTIMATED_FEATURE_BYTES: u64 = 24;
+
+#[derive(Debug, Serialize)]
+pub struct ChromosomePlan {
+    pub reos: u64,
+    // One observation per REO source
+    pub observations: u64,
+    pub sources: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunPlan {
+    pub analysis_accession_id: String,
+    pub reo_count: u64,
+    // Counted by source chromosome
+    pub chromosomes: BTreeMap<String, ChromosomePlan>,
+    // What a chromosome or region build selects: every REO with a source or target on the
+    // chromosome(s), counted over all of its sources
+    pub selected: Option<ChromosomePlan>,
+    // Uncompressed, for the .ecd and .fd files, which make up nearly all of the output
+    pub estimated_output_bytes: u64,
+    pub files: Vec<PathBuf>,
+}
+
+// Checks everything the build needs from the database up front and works out what the build
+// would write, without running the REO queries the full build does.
+pub fn dry_run(
+    options: &Options,
+    client: &mut Client,
+    logger: &Logger,
+) -> Result<DryRunPlan, BuildError> {
+    let reo_count_rows = client.query(
+        r#"
+        SELECT analysis_accession_id, COUNT(*)
+        FROM search_regulatoryeffectobservation
+        WHERE analysis_accession_id = ANY($1)
+        GROUP BY analysis_accession_id"#,
+        &[&options.analysis_accession_ids],
+    )?;
+    for accession_id in &options.analysis_accession_ids {
+        if !reo_count_rows
+            .iter()
+            .any(|row| row.get::<usize, &str>(0) == accession_id)
+        {
+            return Err(BuildError::Data(format!(
+                "Analysis \"{}\" has no regulatory effect observations",
+                accession_id
+            )));
+        }
+    }
+    let reo_count = reo_count_rows
+        .iter()
+        .map(|row| row.get::<usize, i64>(1) as u64)
+        .sum::<u64>();
+    logger.info("Found analysis", json!({ "reos": reo_count }));
+
+    // Report every missing facet at once rather than one per run
+    let mut missing = Vec::new();
+    let facet_rows = client.query(
+        "SELECT id, name FROM search_facet WHERE name = ANY($1)",
+        &[&REQUIRED_FACETS.to_vec()],
+    )?;
+    for name in REQUIRED_FACETS {
+        if !facet_rows
+            .iter()
+            .any(|row| row.get::<&str, &str>("name") == name)
+        {
+            missing.push(format!("facet \"{}\"", name));
+        }
+    }
+    if let Some(dir_facet) = facet_rows
+        .iter()
+        .find(|row| row.get::<&str, &str>("name") == FACET_DIRECTION)
+    {
+        let nonsignificant = client.query(
+            "SELECT id FROM search_facetvalue WHERE facet_id = $1 AND value = 'Non-significant'",
+            &[&dir_facet.get::<&str, i64>("id")],
+        )?;
+        if nonsignificant.is_empty() {
+            missing.push("direction facet value \"Non-significant\"".to_string());
+        }
+    }
+    if !missing.is_empty() {
+        return Err(BuildError::Data(format!(
+            "Missing required {}",
+            missing.join(", ")
+        )));
+    }
+
+    let chromosome_rows = client.query(
+        r#"
+        SELECT sf.chrom_name, COUNT(DISTINCT reo.id) AS reos, COUNT(*) AS observations, COUNT(DISTINCT sf.id) AS sources
+        FROM search_regulatoryeffectobservation AS reo
+        INNER JOIN search_regulatoryeffectobservation_sources AS re_s ON (reo.id = re_s.regulatoryeffectobservation_id)
+        INNER JOIN search_dnafeature AS sf ON (sf.id = re_s.dnafeature_id)
+        WHERE reo.analysis_accession_id = ANY($1)
+        GROUP BY sf.chrom_name"#,
+        &[&options.analysis_accession_ids],
+    )?;
+    let chromosomes: BTreeMap<String, ChromosomePlan> = chromosome_rows
+        .iter()
+        .map(|row| {
+            (
+                row.get::<&str, &str>("chrom_name").to_string(),
+                ChromosomePlan {
+                    reos: row.get::<&str, i64>("reos") as u64,
+                    observations: row.get::<&str, i64>("observations") as u64,
+                    sources: row.get::<&str, i64>("sources") as u64,
+                },
+            )
+        })
+        .collect();
+
+    // Chromosome builds, and region builds without a liftover, select their REOs with the
+    // same predicates. Region builds make the exact selection in memory, and with a liftover
+    // (where the regions' coordinates are in the output assembly) they fetch every REO, so for
+    // them this is an upper bound: the REOs on the regions' chromosomes.
+    let select = |predicate: &str| {
+        format!(
+            r#"
+            SELECT COUNT(DISTINCT search_regulatoryeffectobservation.id) AS reos, COUNT(*) AS observations, COUNT(DISTINCT all_s.dnafeature_id) AS sources
+            FROM search_regulatoryeffectobservation
+            INNER JOIN search_regulatoryeffectobservation_sources AS all_s ON (search_regulatoryeffectobservation.id = all_s.regulatoryeffectobservation_id)
+            WHERE search_regulatoryeffectobservation.analysis_accession_id = ANY($1) and {}"#,
+            predicate
+        )
+    };
+    let selected_row = match &options.chromo {
+        Some(chromo) => Some(client.query_one(
+            &select(REO_ON_CHROMOSOMES),
+            &[&options.analysis_accession_ids, &vec![chromo]],
+        )?),
+        None if options.regions.is_empty() => None,
+        None if options.liftover_chain.is_none() => {
+            let (chrom_names, starts, ends) = region_columns(&options.regions);
+            Some(client.query_one(
+                &select(REO_IN_REGIONS),
+                &[
+                    &options.analysis_accession_ids,
+                    &chrom_names,
+                    &starts,
+                    &ends,
+                ],
+            )?)
+        }
+        None => {
+            let chrom_names: Vec<&str> = options
+                .regions
+                .iter()
+                .map(|region| region.chrom_name.as_str())
+                .collect();
+            Some(client.query_one(
+                &select(REO_ON_CHROMOSOMES),
+                &[&options.analysis_accession_ids, &chrom_names],
+            )?)
+        }
+    };
+    let selected = selected_row.map(|row| ChromosomePlan {
+        reos: row.get::<&str, i64>("reos") as u64,
+        observations: row.get::<&str, i64>("observations") as u64,
+        sources: row.get::<&str, i64>("sources") as u64,
+    });
+
+    let estimated_output_bytes = match &selected {
+        Some(plan) => vec![plan],
+        None => chromosomes.values().collect(),
+    }
+    .into_iter()
+    .map(|plan| {
+        plan.observations * ESTIMATED_OBSERVATION_BYTES + plan.sources * ESTIMATED_FEATURE_BYTES
+    })
+    .sum();
+
+    Ok(DryRunPlan {
+        analysis_accession_id: options.analysis_accession_id.clone(),
+        reo_count,
+        chromosomes,
+        selected,
+        estimated_output_bytes,
+        files: options.output_files(),
+    })
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+
+// Exit codes, so the job runner can tell what kind of failure happened
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_DATABASE: i32 = 3;
+pub const EXIT_DATA: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+
+#[derive(Debug)]
+pub enum BuildError {
+    // Bad arguments or an unsupported option
+    Config(String),
+    // Couldn't connect to or query the database
+    Database(postgres::Error),
+    // The database has data that the build can't handle, e.g., a missing facet
+    Data(String),
+    // Couldn't serialize or write the output
+    Io(io::Error),
+}
+
+impl BuildError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BuildError::Config(_) => EXIT_CONFIG,
+            BuildError::Database(_) => EXIT_DATABASE,
+            BuildError::Data(_) => EXIT_DATA,
+            BuildError::Io(_) => EXIT_IO,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BuildError::Config(_) => "config",
+            BuildError::Database(_) => "database",
+            BuildError::Data(_) => "data",
+            BuildError::Io(_) => "io",
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Config(message) => write!(f, "{}", message),
+            BuildError::Database(e) => write!(f, "Database error: {}", e),
+            BuildError::Data(message) => write!(f, "Data error: {}", message),
+            BuildError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<postgres::Error> for BuildError {
+    fn from(e: postgres::Error) -> Self {
+        BuildError::Database(e)
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(e: io::Error) -> Self {
+        BuildError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for BuildError {
+    fn from(e: bincode::Error) -> Self {
+        BuildError::Io(io::Error::other(e))
+    }
+}
@@ -0,0 +1,156 @@
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use cov_viz_ds::{DbID, ObservationData};
+
+pub const HISTOGRAM_BIN_COUNT: usize = 100;
+
+// The scale a histogram's values, and so its `min`, `max`, and bin edges, are on
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum HistogramScale {
+    // The facet's own values (e.g., effect sizes)
+    Linear,
+    // -log10 of the facet's values. Significances pile up next to zero, so they're binned on
+    // the scale the viewer plots them on; the facet's range64 is still in raw p-values.
+    NegLog10,
+}
+
+// Equal-width bins covering [min, max]. The last bin is closed on both ends so `max` is counted.
+// If every value is the same (zero width), they're all counted in the first bin.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FacetHistogram {
+    pub scale: HistogramScale,
+    pub min: f64,
+    pub max: f64,
+    pub bins: Vec<u64>,
+}
+
+impl FacetHistogram {
+    // `values` are already on `scale`
+    pub fn from_values(values: &[f64], bin_count: usize, scale: HistogramScale) -> Option<Self> {
+        let (min, max) = value_range(values)?;
+        let mut bins = vec![0u64; bin_count];
+        let width = (max - min) / bin_count as f64;
+        for value in values {
+            let idx = if width > 0.0 {
+                (((value - min) / width) as usize).min(bin_count - 1)
+            } else {
+                0
+            };
+            bins[idx] += 1;
+        }
+
+        Some(FacetHistogram {
+            scale,
+            min,
+            max,
+            bins,
+        })
+    }
+}
+
+// Per-facet distribution information for the filter UI. Categorical facets get
+// `value_counts` (facet value id -> number of observations with that value), numeric
+// facets get a `histogram` (see its `scale`). `global_range` is the (min, max) of the numeric
+// facet's raw values across the whole analysis and is only filled in when requested with
+// `--global-ranges`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FacetStats {
+    pub facet_id: DbID,
+    pub value_counts: Option<FxHashMap<DbID, u64>>,
+    pub histogram: Option<FacetHistogram>,
+    pub global_range: Option<(f64, f64)>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExperimentFacetStats {
+    pub facets: Vec<FacetStats>,
+}
+
+pub fn value_range(values: &[f64]) -> Option<(f64, f64)> {
+    let min = values.iter().cloned().reduce(f64::min)?;
+    let max = values.iter().cloned().reduce(f64::max)?;
+
+    Some((min, max))
+}
+
+pub fn facet_value_counts<'a>(
+    observations: impl Iterator<Item = &'a ObservationData>,
+) -> FxHashMap<DbID, u64> {
+    let mut counts: FxHashMap<DbID, u64> = FxHashMap::default();
+    for observation in observations {
+        for facet_value_id in &observation.facet_value_ids {
+            *counts.entry(*facet_value_id).or_insert(0) += 1;
+        }
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(facet_value_ids: Vec<DbID>) -> ObservationData {
+        ObservationData {
+            reo_id: 1,
+            facet_value_ids,
+            source_id: 2,
+            target_id: None,
+            effect_size: 0.0,
+            significance: 1.0,
+            neg_log_significance: 0.0,
+        }
+    }
+
+    #[test]
+    fn histogram_bins() {
+        let histogram =
+            FacetHistogram::from_values(&[0.0, 1.0, 2.5, 9.9, 10.0], 4, HistogramScale::Linear)
+                .unwrap();
+        assert_eq!(histogram.scale, HistogramScale::Linear);
+        assert_eq!((histogram.min, histogram.max), (0.0, 10.0));
+        // [0, 2.5), [2.5, 5), [5, 7.5), [7.5, 10], with the maximum in the last bin
+        assert_eq!(histogram.bins, vec![2, 1, 0, 2]);
+    }
+
+    #[test]
+    fn histogram_negative_values() {
+        let histogram =
+            FacetHistogram::from_values(&[-2.0, -1.0, 0.0, 2.0], 2, HistogramScale::Linear)
+                .unwrap();
+        assert_eq!((histogram.min, histogram.max), (-2.0, 2.0));
+        assert_eq!(histogram.bins, vec![2, 2]);
+    }
+
+    #[test]
+    fn histogram_zero_width() {
+        let histogram =
+            FacetHistogram::from_values(&[3.0, 3.0, 3.0], 10, HistogramScale::NegLog10).unwrap();
+        assert_eq!(histogram.scale, HistogramScale::NegLog10);
+        assert_eq!((histogram.min, histogram.max), (3.0, 3.0));
+        assert_eq!(histogram.bins[0], 3);
+        assert_eq!(histogram.bins.iter().sum::<u64>(), 3);
+        assert_eq!(histogram.bins.len(), 10);
+    }
+
+    #[test]
+    fn histogram_without_values() {
+        assert!(FacetHistogram::from_values(&[], 10, HistogramScale::Linear).is_none());
+        assert_eq!(value_range(&[]), None);
+        assert_eq!(value_range(&[1.5, -3.0, 2.0]), Some((-3.0, 2.0)));
+    }
+
+    #[test]
+    fn value_counts() {
+        let observations = [
+            observation(vec![1, 2]),
+            observation(vec![2]),
+            observation(vec![]),
+        ];
+        let counts = facet_value_counts(observations.iter());
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&1], 1);
+        assert_eq!(counts[&2], 2);
+    }
+}
@@ -0,0 +1,65 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::manifest::{update_manifest_file, StagedFiles};
+use crate::output::{read_output, write_output, FORMAT_VERSION};
+
+// cov_viz --inspect <file>... [--migrate]
+//
+// Prints the header of each output file. Files from an older format version are reported,
+// and rewritten in the current format with --migrate. Files from a newer version are refused.
+// A migrated file replaces the original only once it's completely written, and its entry in
+// the directory's manifest.json, if there is one, gets the new size and checksum.
+pub fn inspect(args: &[String]) -> io::Result<()> {
+    let migrate = args.iter().any(|arg| arg == "--migrate");
+
+    for path in args
+        .iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+    {
+        let file = read_output(&path)?;
+
+        println!("{}", path.display());
+        println!("  format version: {}", file.format_version);
+        println!("  cov_viz version: {}", file.header.cov_viz_version);
+        println!("  build timestamp: {}", file.header.build_timestamp);
+        match &file.header.parameters {
+            Some(parameters) => {
+                println!("  accession: {}", parameters.analysis_accession_id);
+                println!("  assembly: {}", parameters.assembly_name);
+                println!("  bucket size: {}", parameters.bucket_size);
+                println!(
+                    "  chromosome: {}",
+                    parameters.chromosome.as_deref().unwrap_or("all")
+                );
+                if !parameters.regions.is_empty() {
+                    println!("  regions: {}", parameters.regions.join(", "));
+                }
+            }
+            None => println!("  build parameters: unknown"),
+        }
+        println!("  compression: {:?}", file.header.compression);
+        println!(
+            "  payload size (uncompressed): {} bytes",
+            file.payload.len()
+        );
+
+        if file.format_version < FORMAT_VERSION {
+            if migrate {
+                let mut staged = StagedFiles::default();
+                write_output(&staged.stage(&path), &file.header, &file.payload)?;
+                staged.commit()?;
+                update_manifest_file(&path)?;
+                println!("  migrated to format version {}", FORMAT_VERSION);
+            } else {
+                println!(
+                    "  needs migration to format version {} (rerun with --migrate)",
+                    FORMAT_VERSION
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
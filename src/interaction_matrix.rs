@@ -0,0 +1,151 @@
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use cov_viz_ds::{BucketLoc, DbID, ObservationData};
+
+// All the observations linking one source bucket to one target bucket that share the
+// same set of facet values
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Interaction {
+    pub source: BucketLoc,
+    pub target: BucketLoc,
+    // Sorted, so identical facet combinations compare equal
+    pub facet_value_ids: Vec<DbID>,
+    pub count: u64,
+    pub min_significance: f64,
+    pub max_neg_log_significance: f64,
+}
+
+// Sparse source bucket x target bucket matrix, for chord/arc plots and heatmaps
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InteractionMatrix {
+    pub bucket_size: u32,
+    pub interactions: Vec<Interaction>,
+}
+
+pub fn interaction_matrix<'a>(
+    observations: impl Iterator<Item = &'a ObservationData>,
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    bucket_size: u32,
+) -> InteractionMatrix {
+    let mut cells: FxHashMap<(BucketLoc, BucketLoc, Vec<DbID>), Interaction> = FxHashMap::default();
+
+    for observation in observations {
+        // Observations without a target don't link two buckets
+        let target_bucket = match observation
+            .target_id
+            .and_then(|target_id| feature_buckets.get(&target_id))
+        {
+            Some(bucket) => *bucket,
+            None => continue,
+        };
+        let source_bucket = match feature_buckets.get(&observation.source_id) {
+            Some(bucket) => *bucket,
+            None => continue,
+        };
+
+        let mut facet_value_ids = observation.facet_value_ids.clone();
+        facet_value_ids.sort_unstable();
+
+        cells
+            .entry((source_bucket, target_bucket, facet_value_ids.clone()))
+            .and_modify(|cell| {
+                cell.count += 1;
+                cell.min_significance = cell.min_significance.min(observation.significance);
+                cell.max_neg_log_significance = cell
+                    .max_neg_log_significance
+                    .max(observation.neg_log_significance);
+            })
+            .or_insert(Interaction {
+                source: source_bucket,
+                target: target_bucket,
+                facet_value_ids,
+                count: 1,
+                min_significance: observation.significance,
+                max_neg_log_significance: observation.neg_log_significance,
+            });
+    }
+
+    let mut interactions: Vec<Interaction> = cells.into_values().collect();
+    interactions.sort_by(|a, b| {
+        (a.source.chrom, a.source.idx, a.target.chrom, a.target.idx)
+            .cmp(&(b.source.chrom, b.source.idx, b.target.chrom, b.target.idx))
+            .then_with(|| a.facet_value_ids.cmp(&b.facet_value_ids))
+    });
+
+    InteractionMatrix {
+        bucket_size,
+        interactions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(
+        source_id: DbID,
+        target_id: Option<DbID>,
+        facet_value_ids: Vec<DbID>,
+        significance: f64,
+    ) -> ObservationData {
+        ObservationData {
+            reo_id: 1,
+            facet_value_ids,
+            source_id,
+            target_id,
+            effect_size: 0.0,
+            significance,
+            neg_log_significance: -significance.log10(),
+        }
+    }
+
+    fn bucket(chrom: u8, idx: u32) -> BucketLoc {
+        BucketLoc { chrom, idx }
+    }
+
+    #[test]
+    fn groups_by_buckets_and_facet_values() {
+        let feature_buckets: FxHashMap<DbID, BucketLoc> = [
+            (10, bucket(0, 2)),
+            (11, bucket(0, 2)),
+            (12, bucket(0, 1)),
+            (20, bucket(1, 0)),
+            (21, bucket(0, 0)),
+        ]
+        .into_iter()
+        .collect();
+        let observations = [
+            // Same buckets and facet values (in a different order), so one cell
+            observation(10, Some(20), vec![2, 1], 0.01),
+            observation(11, Some(20), vec![1, 2], 0.001),
+            // Same buckets, different facet values
+            observation(10, Some(20), vec![1], 0.1),
+            observation(12, Some(21), vec![], 0.5),
+            // No target, or a target without a bucket, doesn't link two buckets
+            observation(10, None, vec![1], 0.1),
+            observation(10, Some(99), vec![1], 0.1),
+        ];
+
+        let matrix = interaction_matrix(observations.iter(), &feature_buckets, 500);
+        assert_eq!(matrix.bucket_size, 500);
+        let cells: Vec<(BucketLoc, BucketLoc, &[DbID], u64)> = matrix
+            .interactions
+            .iter()
+            .map(|i| (i.source, i.target, i.facet_value_ids.as_slice(), i.count))
+            .collect();
+        // Sorted by source bucket, target bucket, then facet values
+        assert_eq!(
+            cells,
+            vec![
+                (bucket(0, 1), bucket(0, 0), &[][..], 1),
+                (bucket(0, 2), bucket(1, 0), &[1][..], 1),
+                (bucket(0, 2), bucket(1, 0), &[1, 2][..], 2),
+            ]
+        );
+
+        let merged = &matrix.interactions[2];
+        assert_eq!(merged.min_significance, 0.001);
+        assert_eq!(merged.max_neg_log_significance, 3.0);
+    }
+}
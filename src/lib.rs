@@ -0,0 +1,11 @@
+// The cov_viz library: reading (and writing) the files cov_viz builds, for the portal and
+// other tools that consume them. read_output handles the header, format versions, and payload
+// decompression; the payload is then deserialized as the file's type, e.g., a CoverageData for
+// a .ecd file:
+//
+//     let file = cov_viz::output::read_output(path)?;
+//     let coverage: cov_viz_ds::CoverageData = bincode::deserialize(&file.payload)?;
+//
+// .fd files are the exception, holding the two ExperimentFeatureData bitmaps (see
+// feature_data_payload).
+pub mod output;
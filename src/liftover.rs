@@ -0,0 +1,391 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use postgres_range::{BoundType, Range, RangeBound};
+use rustc_hash::FxHashMap;
+
+use cov_viz_ds::DbID;
+
+use crate::build_data::BuildOutput;
+use crate::coordinates::GenomicInterval;
+use crate::manifest::StagedFiles;
+use crate::options::Options;
+
+// Reasons a feature can fail to lift
+pub const UNLIFTED_NO_CHAIN: &str = "chromosome not in chain file";
+pub const UNLIFTED_START: &str = "start not in a chain";
+pub const UNLIFTED_END: &str = "end not in a chain";
+pub const UNLIFTED_SPLIT: &str = "start and end lift through different chains";
+pub const UNLIFTED_RESIZED: &str = "lifted size differs too much from the original";
+
+// A lifted feature's size can be at most this many times bigger or smaller than the original;
+// anything more means the feature spans a large insertion or deletion between the assemblies.
+const MAX_SIZE_CHANGE: f64 = 2.0;
+
+struct Chain {
+    score: f64,
+    // The sequence in the output assembly
+    q_name: String,
+    q_size: i64,
+    // The chain aligns to the reverse strand of q_name
+    q_reverse: bool,
+}
+
+// An ungapped block of a chain: [t_start, t_start + size) in the input assembly lines up with
+// [q_start, q_start + size) in the output assembly (on the reverse strand if the chain is).
+struct Block {
+    t_start: i64,
+    size: i64,
+    q_start: i64,
+    chain: usize,
+}
+
+#[derive(Default)]
+struct ChromBlocks {
+    // Sorted by t_start
+    blocks: Vec<Block>,
+    // Longest block, which bounds how far back a block containing a position can start
+    max_size: i64,
+}
+
+// A UCSC chain file (https://genome.ucsc.edu/goldenPath/help/chain.html) for lifting
+// coordinates from the build's input assembly to its output assembly
+pub struct Liftover {
+    chains: Vec<Chain>,
+    // input assembly chromosome name -> blocks
+    chroms: FxHashMap<String, ChromBlocks>,
+}
+
+// A source or target that couldn't be lifted, in input assembly coordinates
+pub struct UnliftedFeature {
+    // "source" or "target"
+    pub kind: &'static str,
+    pub id: DbID,
+    pub chrom_name: String,
+    pub interval: GenomicInterval,
+    pub reason: &'static str,
+}
+
+#[derive(Debug)]
+pub struct Lifted<'a> {
+    pub chrom_name: &'a str,
+    pub interval: GenomicInterval,
+    // The feature is on the opposite strand in the output assembly
+    pub reversed: bool,
+}
+
+fn invalid_data(line_number: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line_number, message),
+    )
+}
+
+impl Liftover {
+    // Reads a chain file, which may be gzipped (e.g., hg19ToHg38.over.chain.gz)
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let reader: Box<dyn Read> = match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Box::new(MultiGzDecoder::new(file)),
+            _ => Box::new(file),
+        };
+        Liftover::read(BufReader::new(reader))
+    }
+
+    fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut liftover = Liftover {
+            chains: Vec::new(),
+            chroms: FxHashMap::default(),
+        };
+        // (t name, next t position, next q position) of the chain being read
+        let mut current: Option<(String, i64, i64)> = None;
+        for (idx, line) in reader.lines().enumerate() {
+            let line_number = idx + 1;
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+
+            if fields[0] == "chain" {
+                // chain score tName tSize tStrand tStart tEnd qName qSize qStrand qStart qEnd id
+                if fields.len() < 12 {
+                    return Err(invalid_data(line_number, "malformed chain header"));
+                }
+                let number = |i: usize| -> io::Result<i64> {
+                    fields[i]
+                        .parse()
+                        .map_err(|_| invalid_data(line_number, "malformed chain header"))
+                };
+                liftover.chains.push(Chain {
+                    score: fields[1]
+                        .parse()
+                        .map_err(|_| invalid_data(line_number, "malformed chain header"))?,
+                    q_name: fields[7].to_string(),
+                    q_size: number(8)?,
+                    q_reverse: fields[9] == "-",
+                });
+                current = Some((fields[2].to_string(), number(5)?, number(10)?));
+                continue;
+            }
+
+            // size [dt dq]
+            let (t_name, t_position, q_position) = current
+                .as_mut()
+                .ok_or_else(|| invalid_data(line_number, "alignment data outside a chain"))?;
+            let numbers = fields
+                .iter()
+                .map(|f| f.parse::<i64>())
+                .collect::<Result<Vec<i64>, _>>()
+                .map_err(|_| invalid_data(line_number, "malformed alignment data"))?;
+            let size = numbers[0];
+            let chrom_blocks = liftover.chroms.entry(t_name.clone()).or_default();
+            chrom_blocks.blocks.push(Block {
+                t_start: *t_position,
+                size,
+                q_start: *q_position,
+                chain: liftover.chains.len() - 1,
+            });
+            chrom_blocks.max_size = chrom_blocks.max_size.max(size);
+
+            match numbers[..] {
+                [_, dt, dq] => {
+                    *t_position += size + dt;
+                    *q_position += size + dq;
+                }
+                [_] => (),
+                _ => return Err(invalid_data(line_number, "malformed alignment data")),
+            }
+            // A block without gap sizes is the last one in its chain
+            if numbers.len() == 1 {
+                current = None;
+            }
+        }
+
+        for chrom_blocks in liftover.chroms.values_mut() {
+            chrom_blocks.blocks.sort_by_key(|block| block.t_start);
+        }
+
+        Ok(liftover)
+    }
+
+    // Lifts a single base, returning the position in the output assembly (on the chain's
+    // strand) and the chain used. Where chains overlap the highest scoring one wins.
+    fn lift_position(&self, chrom_blocks: &ChromBlocks, position: i64) -> Option<(i64, usize)> {
+        let end = chrom_blocks
+            .blocks
+            .partition_point(|block| block.t_start <= position);
+        chrom_blocks.blocks[..end]
+            .iter()
+            .rev()
+            .take_while(|block| block.t_start + chrom_blocks.max_size > position)
+            .filter(|block| position < block.t_start + block.size)
+            .max_by(|a, b| {
+                self.chains[a.chain]
+                    .score
+                    .total_cmp(&self.chains[b.chain].score)
+            })
+            .map(|block| (block.q_start + (position - block.t_start), block.chain))
+    }
+
+    pub fn lift(
+        &self,
+        chrom_name: &str,
+        interval: GenomicInterval,
+    ) -> Result<Lifted<'_>, &'static str> {
+        let chrom_blocks = self.chroms.get(chrom_name).ok_or(UNLIFTED_NO_CHAIN)?;
+        let (start, start_chain) = self
+            .lift_position(chrom_blocks, interval.start as i64)
+            .ok_or(UNLIFTED_START)?;
+        let (end, end_chain) = self
+            .lift_position(chrom_blocks, interval.end as i64)
+            .ok_or(UNLIFTED_END)?;
+        if start_chain != end_chain {
+            return Err(UNLIFTED_SPLIT);
+        }
+
+        let chain = &self.chains[start_chain];
+        let (start, end) = if chain.q_reverse {
+            (chain.q_size - 1 - end, chain.q_size - 1 - start)
+        } else {
+            (start, end)
+        };
+        let size = (interval.end - interval.start + 1) as f64;
+        let lifted_size = (end - start + 1) as f64;
+        if end < start
+            || lifted_size > size * MAX_SIZE_CHANGE
+            || lifted_size * MAX_SIZE_CHANGE < size
+        {
+            return Err(UNLIFTED_RESIZED);
+        }
+
+        Ok(Lifted {
+            chrom_name: &chain.q_name,
+            interval: GenomicInterval {
+                start: start as i32,
+                end: end as i32,
+            },
+            reversed: chain.q_reverse,
+        })
+    }
+}
+
+impl Lifted<'_> {
+    // The lifted location as a Postgres-style [start, end) range, so it can stand in for the
+    // location read from the database
+    pub fn range(&self) -> Range<i32> {
+        let (start, end) = self.interval.half_open();
+        Range::new(
+            Some(RangeBound::new(start, BoundType::Inclusive)),
+            Some(RangeBound::new(end, BoundType::Exclusive)),
+        )
+    }
+}
+
+// Writes the features that failed to lift to <name>_unlifted.tsv, in 0-based, half-open
+// input assembly coordinates
+pub fn export_unlifted(
+    output: &BuildOutput,
+    options: &Options,
+    staged: &mut StagedFiles,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(
+        staged.stage(&options.export_path("unlifted.tsv")),
+    )?);
+    writeln!(writer, "#chrom\tstart\tend\tkind\tid\treason")?;
+    for feature in &output.unlifted_features {
+        let (start, end) = feature.interval.half_open();
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            feature.chrom_name, start, end, feature.kind, feature.id, feature.reason
+        )?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // chr1 lifts to chr1 on the + strand through two blocks with a gap between them:
+    //   [0, 100) -> [500, 600), a 10 base gap (20 in the output), [110, 160) -> [620, 670)
+    // chr2 lifts to chr2 on the - strand: [0, 100) -> [200, 300) on the reverse strand of a
+    // 1000 base chromosome, which is [700, 800) on the forward strand
+    // chr3 lifts through two separate chains: [0, 100) -> [0, 100) and [100, 200) -> [300, 400)
+    // chr4 has a 100 base insertion in the output: [0, 10) -> [0, 10), [10, 20) -> [110, 120)
+    const CHAIN: &str = "\
+chain 1000 chr1 1000 + 0 160 chr1 2000 + 500 670 1
+100 10 20
+50
+
+chain 500 chr2 1000 + 0 100 chr2 1000 - 200 300 2
+100
+
+chain 100 chr3 1000 + 0 100 chr3 1000 + 0 100 3
+100
+
+chain 100 chr3 1000 + 100 200 chr3 1000 + 300 400 4
+100
+
+chain 20 chr4 1000 + 0 20 chr4 1000 + 0 120 5
+10 0 100
+10
+";
+
+    fn liftover() -> Liftover {
+        Liftover::read(CHAIN.as_bytes()).unwrap()
+    }
+
+    fn interval(start: i32, end: i32) -> GenomicInterval {
+        GenomicInterval { start, end }
+    }
+
+    #[test]
+    fn forward_strand() {
+        let liftover = liftover();
+
+        let lifted = liftover.lift("chr1", interval(10, 19)).unwrap();
+        assert_eq!(lifted.chrom_name, "chr1");
+        assert_eq!(lifted.interval, interval(510, 519));
+        assert!(!lifted.reversed);
+
+        // Across the gap, so the feature grows by the gap's difference in size
+        let lifted = liftover.lift("chr1", interval(95, 114)).unwrap();
+        assert_eq!(lifted.interval, interval(595, 624));
+
+        // The last base of the chain
+        let lifted = liftover.lift("chr1", interval(150, 159)).unwrap();
+        assert_eq!(lifted.interval, interval(660, 669));
+        assert_eq!(
+            lifted.range(),
+            Range::new(
+                Some(RangeBound::new(660, BoundType::Inclusive)),
+                Some(RangeBound::new(670, BoundType::Exclusive)),
+            )
+        );
+    }
+
+    #[test]
+    fn reverse_strand() {
+        let liftover = liftover();
+        let lifted = liftover.lift("chr2", interval(10, 19)).unwrap();
+        assert_eq!(lifted.chrom_name, "chr2");
+        assert_eq!(lifted.interval, interval(780, 789));
+        assert!(lifted.reversed);
+    }
+
+    #[test]
+    fn gaps() {
+        let liftover = liftover();
+        assert_eq!(
+            liftover.lift("chr1", interval(102, 108)).err(),
+            Some(UNLIFTED_START)
+        );
+        assert_eq!(
+            liftover.lift("chr1", interval(102, 120)).err(),
+            Some(UNLIFTED_START)
+        );
+        assert_eq!(
+            liftover.lift("chr1", interval(50, 105)).err(),
+            Some(UNLIFTED_END)
+        );
+        assert_eq!(
+            liftover.lift("chr1", interval(150, 160)).err(),
+            Some(UNLIFTED_END)
+        );
+        assert_eq!(
+            liftover.lift("chr5", interval(0, 10)).err(),
+            Some(UNLIFTED_NO_CHAIN)
+        );
+    }
+
+    #[test]
+    fn split_features() {
+        let liftover = liftover();
+        assert_eq!(
+            liftover.lift("chr3", interval(90, 109)).err(),
+            Some(UNLIFTED_SPLIT)
+        );
+        // Either side of the split lifts on its own
+        assert_eq!(
+            liftover.lift("chr3", interval(90, 99)).unwrap().interval,
+            interval(90, 99)
+        );
+        assert_eq!(
+            liftover.lift("chr3", interval(100, 109)).unwrap().interval,
+            interval(300, 309)
+        );
+    }
+
+    #[test]
+    fn resized_features() {
+        let liftover = liftover();
+        assert_eq!(
+            liftover.lift("chr4", interval(5, 14)).err(),
+            Some(UNLIFTED_RESIZED)
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+// How many progress lines a long loop reports, e.g., 10 -> every 10%
+const PROGRESS_STEPS: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!(
+                "Invalid log level {}. Must be \"error\", \"warn\", \"info\", or \"debug\"",
+                s
+            )),
+        }
+    }
+}
+
+impl LogLevel {
+    fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Invalid log format {}. Must be \"text\" or \"json\"",
+                s
+            )),
+        }
+    }
+}
+
+// Log lines go to stderr so they end up next to the JSON status line the job runner reads.
+// Each line has a message and a set of fields (counts, timings, etc.) which are written as
+// key=value pairs in text format and as extra keys in JSON format.
+pub struct Logger {
+    level: LogLevel,
+    format: LogFormat,
+    start: Instant,
+}
+
+impl Logger {
+    pub fn new(level: LogLevel, format: LogFormat) -> Self {
+        Logger {
+            level,
+            format,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn log(&self, level: LogLevel, message: &str, fields: Value) {
+        if level > self.level {
+            return;
+        }
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let fields = match fields {
+            Value::Object(fields) => fields,
+            _ => Map::new(),
+        };
+        match self.format {
+            LogFormat::Text => {
+                let mut line = format!("[{:>9.3}s] {:<5} {}", elapsed, level.name(), message);
+                for (key, value) in &fields {
+                    match value {
+                        Value::String(value) => line.push_str(&format!(" {}={}", key, value)),
+                        value => line.push_str(&format!(" {}={}", key, value)),
+                    }
+                }
+                eprintln!("{}", line);
+            }
+            LogFormat::Json => {
+                let mut line = Map::new();
+                line.insert(
+                    "timestamp".to_string(),
+                    json!(SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs_f64())
+                        .unwrap_or(0.0)),
+                );
+                line.insert("elapsed_s".to_string(), json!(elapsed));
+                line.insert("level".to_string(), json!(level.name()));
+                line.insert("message".to_string(), json!(message));
+                line.extend(fields);
+                eprintln!("{}", Value::Object(line));
+            }
+        }
+    }
+
+    pub fn error(&self, message: &str, fields: Value) {
+        self.log(LogLevel::Error, message, fields);
+    }
+
+    pub fn warn(&self, message: &str, fields: Value) {
+        self.log(LogLevel::Warn, message, fields);
+    }
+
+    pub fn info(&self, message: &str, fields: Value) {
+        self.log(LogLevel::Info, message, fields);
+    }
+
+    pub fn debug(&self, message: &str, fields: Value) {
+        self.log(LogLevel::Debug, message, fields);
+    }
+
+    // Starts timing a phase of the build; the timing is logged when the phase is finished
+    pub fn phase(&self, name: &'static str) -> Phase<'_> {
+        self.debug("Phase started", json!({ "phase": name }));
+        Phase {
+            logger: self,
+            name,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn progress(&self, name: &'static str, total: u64) -> Progress<'_> {
+        Progress {
+            logger: self,
+            name,
+            total,
+            done: 0,
+            next_report: total.div_ceil(PROGRESS_STEPS).max(1),
+            start: Instant::now(),
+        }
+    }
+}
+
+pub struct Phase<'a> {
+    logger: &'a Logger,
+    name: &'static str,
+    start: Instant,
+}
+
+impl Phase<'_> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // `fields` are logged along with the timing, e.g., how many rows a query returned
+    pub fn finish(self, fields: Value) -> f64 {
+        let elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        let mut line = Map::new();
+        line.insert("phase".to_string(), json!(self.name));
+        line.insert("elapsed_ms".to_string(), json!(elapsed_ms.round()));
+        if let Value::Object(fields) = fields {
+            line.extend(fields);
+        }
+        self.logger.info("Phase finished", Value::Object(line));
+        elapsed_ms
+    }
+}
+
+// Reports how far through a long loop the build is, every 1/PROGRESS_STEPS of the way
+pub struct Progress<'a> {
+    logger: &'a Logger,
+    name: &'static str,
+    total: u64,
+    done: u64,
+    next_report: u64,
+    start: Instant,
+}
+
+impl Progress<'_> {
+    pub fn tick(&mut self) {
+        self.done += 1;
+        if self.done < self.next_report && self.done < self.total {
+            return;
+        }
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.done as f64 / elapsed
+        } else {
+            0.0
+        };
+        let remaining_s = if rate > 0.0 {
+            (self.total - self.done) as f64 / rate
+        } else {
+            0.0
+        };
+        self.logger.info(
+            "Progress",
+            json!({
+                "phase": self.name,
+                "done": self.done,
+                "total": self.total,
+                "percent": (self.done * 100) / self.total.max(1),
+                "per_second": rate.round(),
+                "remaining_s": remaining_s.round(),
+            }),
+        );
+        self.next_report += self.total.div_ceil(PROGRESS_STEPS).max(1);
+    }
+}
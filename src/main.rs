@@ -1,26 +1,231 @@
+mod bed;
+mod bucket_summary;
 mod build_data;
+mod build_stats;
+mod compare;
+mod contigs;
+mod coordinates;
+mod dry_run;
+mod error;
+mod facet_stats;
+mod inspect;
+mod interaction_matrix;
+mod liftover;
+mod logging;
+mod manifest;
+#[cfg(feature = "parquet")]
+mod observation_table;
 mod options;
+mod regions;
+mod validate;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
 
 use postgres::{Client, NoTls};
+use serde_json::json;
+
+// The output file format lives in the library so other tools can read the files; this makes it
+// crate::output here too
+use cov_viz::output;
+
+use crate::bed::{export_bed, export_links};
+use crate::build_data::{build_data, BuildOutput};
+use crate::compare::compare;
+use crate::dry_run::dry_run;
+use crate::error::{BuildError, EXIT_DATA, EXIT_IO};
+use crate::inspect::inspect;
+use crate::liftover::export_unlifted;
+use crate::logging::Logger;
+use crate::manifest::{update_manifest, StagedFiles};
+#[cfg(feature = "parquet")]
+use crate::observation_table::export_observation_table;
+use crate::options::{Export, Options};
+use crate::output::{feature_data_payload, write_output};
+use crate::validate::validate;
+
+// Everything is staged first and only moved into place once all the files have been
+// written, then recorded in the manifest.
+fn write_outputs(
+    output: &BuildOutput,
+    options: &Options,
+    logger: &Logger,
+) -> Result<Vec<PathBuf>, BuildError> {
+    let header = options.file_header();
+    let mut stats = output.stats.clone();
+    let phase = logger.phase("serialization");
+    let mut staged = StagedFiles::default();
+
+    write_output(
+        &staged.stage(&options.features_output_location),
+        &header,
+        &feature_data_payload(&output.features)?,
+    )?;
+    write_output(
+        &staged.stage(&options.cov_output_location),
+        &header,
+        &bincode::serialize(&output.coverage)?,
+    )?;
+    write_output(
+        &staged.stage(&options.facet_stats_output_location),
+        &header,
+        &bincode::serialize(&output.facet_stats)?,
+    )?;
+    write_output(
+        &staged.stage(&options.summary_output_location),
+        &header,
+        &bincode::serialize(&output.summary)?,
+    )?;
+    write_output(
+        &staged.stage(&options.interactions_output_location),
+        &header,
+        &bincode::serialize(&output.interactions)?,
+    )?;
+
+    stats
+        .timings_ms
+        .insert(phase.name(), phase.finish(json!({})));
+
+    let phase = logger.phase("exports");
+    let exports = options.exports();
+    // export_bed also writes the BigBed files, export_links both kinds of links, and
+    // export_observation_table both table formats
+    if exports.contains(&Export::Bed) {
+        export_bed(output, options, &mut staged)?;
+    }
+    if exports.contains(&Export::Bedpe) || exports.contains(&Export::Interact) {
+        export_links(output, options, &mut staged)?;
+    }
+    if exports.contains(&Export::Unlifted) {
+        export_unlifted(output, options, &mut staged)?;
+    }
+    // Options::get refuses these exports without the parquet feature
+    #[cfg(feature = "parquet")]
+    if exports.contains(&Export::Parquet) || exports.contains(&Export::Arrow) {
+        export_observation_table(output, options, &mut staged)?;
+    }
+
+    stats
+        .timings_ms
+        .insert(phase.name(), phase.finish(json!({})));
+
+    if exports.contains(&Export::Stats) {
+        fs::write(
+            staged.stage(&options.export_path("stats.json")),
+            serde_json::to_vec_pretty(&stats).map_err(std::io::Error::other)?,
+        )?;
+    }
+    debug_assert_eq!(
+        staged.final_paths().collect::<BTreeSet<_>>(),
+        options
+            .output_files()
+            .iter()
+            .map(PathBuf::as_path)
+            .collect(),
+        "the files written don't match Options::output_files"
+    );
+
+    let files = staged.commit()?;
+    update_manifest(options, &header, &files)?;
+    logger.info("Wrote output files", json!({ "count": files.len() }));
 
-use crate::build_data::build_data;
-use crate::options::Options;
+    Ok(files)
+}
+
+fn run(options: &Options, logger: &Logger) -> Result<Vec<PathBuf>, BuildError> {
+    let mut client = Client::connect(&options.connection_string, NoTls)?;
+    if options.dry_run {
+        let plan = dry_run(options, &mut client, logger)?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&plan).map_err(std::io::Error::other)?
+        );
+        return Ok(Vec::new());
+    }
+    let output = build_data(options, &mut client, logger)?;
+    write_outputs(&output, options, logger)
+}
 
 fn main() {
-    let options = Options::get();
+    // The other modes are picked with a flag rather than a leading word, which could just as
+    // well be the output directory
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mode_args =
+        |mode: &str| -> Vec<String> { args.iter().filter(|arg| *arg != mode).cloned().collect() };
+    if args.iter().any(|arg| arg == "--inspect") {
+        if let Err(e) = inspect(&mode_args("--inspect")) {
+            eprintln!("{}", e);
+            process::exit(EXIT_IO);
+        }
+        return;
+    }
+    // validate is a subcommand, so a build into a directory named "validate" has to spell it
+    // "./validate"
+    if args.first().map(String::as_str) == Some("validate") {
+        match validate(&args[1..]) {
+            Ok(0) => (),
+            Ok(_) => process::exit(EXIT_DATA),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(e.exit_code());
+            }
+        }
+        return;
+    }
+    if args.iter().any(|arg| arg == "--compare") {
+        match compare(&mode_args("--compare")) {
+            Ok(0) => (),
+            Ok(_) => process::exit(EXIT_DATA),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(e.exit_code());
+            }
+        }
+        return;
+    }
 
-    let mut client = match Client::connect(&options.connection_string, NoTls) {
-        Ok(client) => client,
+    // The last line on stderr is always a JSON status line for the job runner, even when the
+    // arguments themselves are invalid
+    let options = match Options::get() {
+        Ok(options) => options,
         Err(e) => {
-            panic!("{}", e)
+            eprintln!("{}", e);
+            exit_with_error(args.get(1).map(String::as_str), &e);
         }
     };
+    let logger = Logger::new(options.log_level, options.log_format);
 
-    match build_data(&options, &mut client) {
-        Ok((coverage, features)) => {
-            coverage.serialize(&options.cov_output_location);
-            features.serialize(&options.features_output_location);
+    match run(&options, &logger) {
+        Ok(files) => eprintln!(
+            "{}",
+            json!({
+                "status": "success",
+                "accession": options.analysis_accession_id,
+                "dry_run": options.dry_run,
+                "files": files,
+            })
+        ),
+        Err(e) => {
+            logger.error(&e.to_string(), json!({ "error": e.kind() }));
+            exit_with_error(Some(&options.analysis_accession_id), &e);
         }
-        Err(e) => eprintln!("{}", e),
-    };
+    }
+}
+
+// Writes the error status line and exits with the error's exit code
+fn exit_with_error(accession: Option<&str>, e: &BuildError) -> ! {
+    eprintln!(
+        "{}",
+        json!({
+            "status": "error",
+            "accession": accession,
+            "error": e.kind(),
+            "message": e.to_string(),
+            "exit_code": e.exit_code(),
+        })
+    );
+    process::exit(e.exit_code());
 }
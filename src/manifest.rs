@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::options::Options;
+use crate::output::{BuildParameters, FileHeader};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const MANIFEST_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const MANIFEST_LOCK_RETRY: Duration = Duration::from_millis(100);
+
+// Output files are written to temporary files next to their final location and only renamed
+// into place once everything has been written, so a failed or interrupted build never leaves
+// a partial file (or a new .ecd next to an old .fd) where the portal will find it.
+//
+// The files share the output directory with other builds' files, so they can't be swapped in
+// with a single rename. A crash during commit() itself, between two renames, can still leave
+// a mix of old and new files. The manifest is only updated after commit() finishes, so the
+// mix doesn't match the checksums the manifest lists for the build.
+#[derive(Default)]
+pub struct StagedFiles {
+    // (temporary path, final path)
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl StagedFiles {
+    // Returns the temporary path to write in place of `final_path`
+    pub fn stage(&mut self, final_path: &Path) -> PathBuf {
+        let file_name = final_path.file_name().unwrap().to_string_lossy();
+        let temp_name = format!(".{}.{}.tmp", file_name, process::id());
+        let temp_path = final_path.with_file_name(temp_name);
+        self.files
+            .push((temp_path.clone(), final_path.to_path_buf()));
+        temp_path
+    }
+
+    pub fn final_paths(&self) -> impl Iterator<Item = &Path> {
+        self.files
+            .iter()
+            .map(|(_, final_path)| final_path.as_path())
+    }
+
+    // Moves every staged file into place, returning the final paths. Each file is synced to
+    // disk before it's renamed, and its directory after, so a crash right after commit() can't
+    // leave the new names pointing at empty or partly written files, or lose the renames.
+    // That covers every staged file, including the ones written by other code (e.g.,
+    // bedToBigBed) and plain fs::write calls (the stats and manifest files).
+    pub fn commit(mut self) -> io::Result<Vec<PathBuf>> {
+        let files = std::mem::take(&mut self.files);
+        for (temp_path, _) in &files {
+            File::open(temp_path)?.sync_all()?;
+        }
+        let mut directories = BTreeSet::new();
+        for (temp_path, final_path) in &files {
+            fs::rename(temp_path, final_path)?;
+            directories.insert(final_path.parent().unwrap_or(Path::new("")));
+        }
+        for directory in directories {
+            sync_directory(directory)?;
+        }
+
+        Ok(files
+            .into_iter()
+            .map(|(_, final_path)| final_path)
+            .collect())
+    }
+}
+
+// Makes renames in the directory durable. Directories can only be opened (and so synced) like
+// this on Unix.
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    let directory = if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    };
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+impl Drop for StagedFiles {
+    // Anything still staged didn't get committed, so clean it up
+    fn drop(&mut self) {
+        for (temp_path, _) in &self.files {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ManifestFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ManifestBuild {
+    pub cov_viz_version: String,
+    pub build_timestamp: u64,
+    pub parameters: Option<BuildParameters>,
+    pub files: Vec<ManifestFile>,
+}
+
+// manifest.json lists the files from every build written to the output directory, keyed
+// by the build's output name ("level1", "level2_<chromosome>"), since the chromosome
+// builds all share a directory.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    pub builds: BTreeMap<String, ManifestBuild>,
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// Held while the manifest is being updated so concurrent builds don't lose each other's
+// entries. It's an advisory lock on manifest.json.lock, which the OS releases when the file is
+// closed, including when the process is killed, so a crashed build can't leave a stale lock.
+// The lock file itself is left in place.
+struct ManifestLock {
+    file: File,
+}
+
+impl ManifestLock {
+    fn acquire(output_directory: &Path) -> io::Result<Self> {
+        let path = output_directory.join(format!("{}.lock", MANIFEST_FILE));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let start = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(ManifestLock { file }),
+                Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                    if start.elapsed() > MANIFEST_LOCK_TIMEOUT {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("Timed out waiting for {}", path.display()),
+                        ));
+                    }
+                    thread::sleep(MANIFEST_LOCK_RETRY);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for ManifestLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn read_manifest(manifest_path: &Path) -> io::Result<Manifest> {
+    match fs::read(manifest_path) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_manifest(manifest_path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let mut staged = StagedFiles::default();
+    let temp_path = staged.stage(manifest_path);
+    fs::write(
+        &temp_path,
+        serde_json::to_vec_pretty(manifest).map_err(io::Error::other)?,
+    )?;
+    staged.commit()?;
+
+    Ok(())
+}
+
+fn manifest_file(path: &Path) -> io::Result<ManifestFile> {
+    Ok(ManifestFile {
+        name: path.file_name().unwrap().to_string_lossy().to_string(),
+        size: fs::metadata(path)?.len(),
+        sha256: sha256(path)?,
+    })
+}
+
+pub fn update_manifest(
+    options: &Options,
+    header: &FileHeader,
+    files: &[PathBuf],
+) -> io::Result<()> {
+    let manifest_files = files
+        .iter()
+        .map(|path| manifest_file(path))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let _lock = ManifestLock::acquire(&options.output_directory)?;
+    let manifest_path = options.output_directory.join(MANIFEST_FILE);
+    let mut manifest = read_manifest(&manifest_path)?;
+    manifest.builds.insert(
+        options.output_stem.clone(),
+        ManifestBuild {
+            cov_viz_version: header.cov_viz_version.clone(),
+            build_timestamp: header.build_timestamp,
+            parameters: header.parameters.clone(),
+            files: manifest_files,
+        },
+    );
+
+    write_manifest(&manifest_path, &manifest)
+}
+
+// Updates the size and checksum of a file that was rewritten outside a build (e.g., by
+// --inspect --migrate) in the manifest of the directory it's in. Files the manifest doesn't
+// list, and directories without a manifest, are left alone.
+pub fn update_manifest_file(path: &Path) -> io::Result<()> {
+    let output_directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    let manifest_path = output_directory.join(MANIFEST_FILE);
+    if !manifest_path.exists() {
+        return Ok(());
+    }
+
+    let updated = manifest_file(path)?;
+    let _lock = ManifestLock::acquire(output_directory)?;
+    let mut manifest = read_manifest(&manifest_path)?;
+    let mut listed = false;
+    for file in manifest
+        .builds
+        .values_mut()
+        .flat_map(|build| build.files.iter_mut())
+        .filter(|file| file.name == updated.name)
+    {
+        file.size = updated.size;
+        file.sha256 = updated.sha256.clone();
+        listed = true;
+    }
+    if listed {
+        write_manifest(&manifest_path, &manifest)?;
+    }
+
+    Ok(())
+}
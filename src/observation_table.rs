@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Float32Array, Float64Array, ListBuilder, StringArray, StringBuilder,
+    UInt32Array, UInt64Array,
+};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+
+use cov_viz_ds::ObservationData;
+
+use crate::build_data::BuildOutput;
+use crate::manifest::StagedFiles;
+use crate::options::Options;
+
+// The observations that feed the visualizer as one flat table, one row per observation, with
+// the coordinates and facet value names filled in so it can be used without the database.
+pub fn observation_table(output: &BuildOutput) -> Result<RecordBatch, ArrowError> {
+    let facet_names = output.facet_value_names();
+    let observations: Vec<(&ObservationData, bool)> = output
+        .coverage
+        .significant_observations
+        .iter()
+        .map(|o| (o, true))
+        .chain(
+            output
+                .coverage
+                .nonsignificant_observations
+                .iter()
+                .map(|o| (o, false)),
+        )
+        .collect();
+
+    let source_info = |o: &ObservationData| output.feature_info.get(&o.source_id);
+    let target_info = |o: &ObservationData| {
+        o.target_id
+            .and_then(|target_id| output.feature_info.get(&target_id))
+    };
+    let chrom_name = |sequence: u32| output.sequences[sequence as usize].0.as_str();
+    let bucket = |id| output.coverage.feature_buckets.get(&id).map(|b| b.idx);
+
+    let mut facet_values = ListBuilder::new(StringBuilder::new());
+    for (observation, _) in &observations {
+        let mut names: Vec<&str> = observation
+            .facet_value_ids
+            .iter()
+            .filter_map(|id| facet_names.get(id).cloned())
+            .collect();
+        names.sort_unstable();
+        for name in names {
+            facet_values.values().append_value(name);
+        }
+        facet_values.append(true);
+    }
+
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        (
+            "reo_id",
+            Arc::new(UInt64Array::from_iter_values(
+                observations.iter().map(|(o, _)| o.reo_id),
+            )),
+        ),
+        (
+            "significant",
+            Arc::new(BooleanArray::from_iter(
+                observations
+                    .iter()
+                    .map(|(_, significant)| Some(*significant)),
+            )),
+        ),
+        (
+            "source_id",
+            Arc::new(UInt64Array::from_iter_values(
+                observations.iter().map(|(o, _)| o.source_id),
+            )),
+        ),
+        (
+            "source_chrom",
+            Arc::new(StringArray::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| source_info(o).map(|f| chrom_name(f.sequence))),
+            )),
+        ),
+        (
+            "source_start",
+            Arc::new(UInt32Array::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| source_info(o).map(|f| f.start)),
+            )),
+        ),
+        (
+            "source_end",
+            Arc::new(UInt32Array::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| source_info(o).map(|f| f.end)),
+            )),
+        ),
+        (
+            "source_bucket",
+            Arc::new(UInt32Array::from_iter(
+                observations.iter().map(|(o, _)| bucket(o.source_id)),
+            )),
+        ),
+        (
+            "target_id",
+            Arc::new(UInt64Array::from_iter(
+                observations.iter().map(|(o, _)| o.target_id),
+            )),
+        ),
+        (
+            "target_chrom",
+            Arc::new(StringArray::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| target_info(o).map(|f| chrom_name(f.sequence))),
+            )),
+        ),
+        (
+            "target_start",
+            Arc::new(UInt32Array::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| target_info(o).map(|f| f.start)),
+            )),
+        ),
+        (
+            "target_end",
+            Arc::new(UInt32Array::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| target_info(o).map(|f| f.end)),
+            )),
+        ),
+        (
+            "target_bucket",
+            Arc::new(UInt32Array::from_iter(
+                observations
+                    .iter()
+                    .map(|(o, _)| o.target_id.and_then(bucket)),
+            )),
+        ),
+        (
+            "effect_size",
+            Arc::new(Float32Array::from_iter_values(
+                observations.iter().map(|(o, _)| o.effect_size),
+            )),
+        ),
+        (
+            "significance",
+            Arc::new(Float64Array::from_iter_values(
+                observations.iter().map(|(o, _)| o.significance),
+            )),
+        ),
+        (
+            "neg_log_significance",
+            Arc::new(Float64Array::from_iter_values(
+                observations.iter().map(|(o, _)| o.neg_log_significance),
+            )),
+        ),
+        (
+            "distance",
+            Arc::new(UInt32Array::from_iter(observations.iter().map(|(o, _)| {
+                output
+                    .observation_distances
+                    .get(&(o.reo_id, o.source_id))
+                    .cloned()
+            }))),
+        ),
+        ("facet_values", Arc::new(facet_values.finish())),
+    ];
+
+    RecordBatch::try_from_iter(columns)
+}
+
+pub fn write_parquet(table: &RecordBatch, path: &Path) -> Result<(), ParquetError> {
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, table.schema(), Some(properties))?;
+    writer.write(table)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+pub fn write_arrow(table: &RecordBatch, path: &Path) -> Result<(), ArrowError> {
+    let mut writer = FileWriter::try_new(File::create(path)?, &table.schema())?;
+    writer.write(table)?;
+    writer.finish()
+}
+
+pub fn export_observation_table(
+    output: &BuildOutput,
+    options: &Options,
+    staged: &mut StagedFiles,
+) -> io::Result<()> {
+    let table = observation_table(output).map_err(io::Error::other)?;
+    if options.parquet_export {
+        write_parquet(
+            &table,
+            &staged.stage(&options.export_path("observations.parquet")),
+        )
+        .map_err(io::Error::other)?;
+    }
+    if options.arrow_export {
+        write_arrow(
+            &table,
+            &staged.stage(&options.export_path("observations.arrow")),
+        )
+        .map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
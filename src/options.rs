@@ -1,54 +1,415 @@
 use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
+use std::str::FromStr;
 
-const DATABASE_URL_KEY: &str = "DATABASE_URL";
+use crate::build_data::select_assembly;
+use crate::contigs::ContigPolicy;
+use crate::coordinates::BoundsPolicy;
+use crate::error::BuildError;
+use crate::logging::{LogFormat, LogLevel};
+use crate::output::{BuildParameters, Compression, FileHeader};
+use crate::regions::{read_bed_regions, Region};
+
+pub const DATABASE_URL_KEY: &str = "DATABASE_URL";
+const USAGE: &str = "Usage: cov_viz <output directory> <experiment accession id> <assembly name> [bucket size] [chromosome] [flags]";
+
+// Every flag a build takes: the switches, and the --name=value flags by their prefix. Anything
+// else starting with "--" is refused, so a misspelled flag doesn't silently build without it.
+const SWITCHES: [&str; 8] = [
+    "--global-ranges",
+    "--bed",
+    "--bigbed",
+    "--bedpe",
+    "--interact",
+    "--parquet",
+    "--arrow",
+    "--dry-run",
+];
+const VALUE_FLAGS: [&str; 9] = [
+    "--compression=",
+    "--log-level=",
+    "--log-format=",
+    "--out-of-bounds=",
+    "--contigs=",
+    "--liftover=",
+    "--output-assembly=",
+    "--region=",
+    "--regions=",
+];
+
+const DEFAULT_BUCKET_SIZE: u32 = 2_000_000;
+// Region builds are for locus-level views, so they default to much finer buckets
+const DEFAULT_REGION_BUCKET_SIZE: u32 = 10_000;
 
 #[derive(Debug)]
 pub struct Options {
+    pub output_directory: PathBuf,
+    // "level1", "level2_<chromosome>", "region_<chromosome>_<start>_<end>" for a single
+    // region, or "regions"; every output file name starts with this
+    pub output_stem: String,
     pub cov_output_location: PathBuf,
     pub features_output_location: PathBuf,
+    pub facet_stats_output_location: PathBuf,
+    pub summary_output_location: PathBuf,
+    pub interactions_output_location: PathBuf,
+    // As given on the command line; several accessions are separated by commas
     pub analysis_accession_id: String,
+    // The analyses merged into the build, each once, in the order given
+    pub analysis_accession_ids: Vec<String>,
     pub assembly_name: String,
     pub connection_string: String,
     pub bucket_size: u32,
     pub chromo: Option<String>,
+    // Only REOs with a source or target in one of these regions are built; empty means no
+    // restriction
+    pub regions: Vec<Region>,
+    pub global_ranges: bool,
+    pub bed_export: bool,
+    pub bigbed_export: bool,
+    pub bedpe_export: bool,
+    pub interact_export: bool,
+    pub parquet_export: bool,
+    pub arrow_export: bool,
+    pub compression: Compression,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub dry_run: bool,
+    pub bounds_policy: BoundsPolicy,
+    pub contig_policy: ContigPolicy,
+    // Chain file for lifting coordinates from `assembly_name` to `output_assembly_name`
+    pub liftover_chain: Option<PathBuf>,
+    // The assembly the output coordinates are in; the same as `assembly_name` without a liftover
+    pub output_assembly_name: String,
+}
+
+// The optional outputs, which write files named <output stem>_<file name>. write_outputs
+// writes the exports Options::exports lists, and the dry run reports their files, so the two
+// can't disagree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Export {
+    Bed,
+    // Requires (and is written along with) Bed
+    BigBed,
+    Bedpe,
+    Interact,
+    Parquet,
+    Arrow,
+    Unlifted,
+    Stats,
+}
+
+impl Export {
+    pub fn file_names(self) -> &'static [&'static str] {
+        match self {
+            Export::Bed => &[
+                "chrom.sizes",
+                "sources.bed",
+                "targets.bed",
+                "sources.bedGraph",
+                "targets.bedGraph",
+            ],
+            Export::BigBed => &["features.as", "sources.bb", "targets.bb"],
+            Export::Bedpe => &["observations.bedpe"],
+            Export::Interact => &["observations.interact"],
+            Export::Parquet => &["observations.parquet"],
+            Export::Arrow => &["observations.arrow"],
+            Export::Unlifted => &["unlifted.tsv"],
+            Export::Stats => &["stats.json"],
+        }
+    }
+}
+
+// Parses a --name=value flag's value, e.g., flag_value(&flags, "--contigs=", ContigPolicy::Exclude)
+fn flag_value<T: FromStr<Err = String>>(
+    flags: &[String],
+    prefix: &str,
+    default: T,
+) -> Result<T, BuildError> {
+    match flags.iter().find_map(|flag| flag.strip_prefix(prefix)) {
+        Some(value) => value.parse().map_err(BuildError::Config),
+        None => Ok(default),
+    }
+}
+
+// Splits a comma-separated list of analysis accession ids, keeping each id once, in the order
+// given, so a repeated id doesn't add a second analysis facet value
+pub fn split_accession_ids(accession_ids: &str) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in accession_ids.split(',') {
+        if !ids.iter().any(|seen| seen == id) {
+            ids.push(id.to_string());
+        }
+    }
+    ids
 }
 
 impl Options {
-    pub fn get() -> Self {
+    pub fn get() -> Result<Self, BuildError> {
         let env_args: HashMap<String, String> = env::vars().collect();
-        let args: Vec<String> = env::args().collect();
+        // Flags (e.g., --global-ranges) can go anywhere; everything else is positional
+        let (flags, args): (Vec<String>, Vec<String>) =
+            env::args().partition(|arg| arg.starts_with("--"));
+        if let Some(flag) = flags.iter().find(|flag| {
+            !SWITCHES.contains(&flag.as_str())
+                && !VALUE_FLAGS.iter().any(|prefix| flag.starts_with(prefix))
+        }) {
+            return Err(BuildError::Config(format!("Unknown flag {}", flag)));
+        }
 
-        let output_location = &args[1];
-        let chromo = match args.get(5) {
-            Some(chrom_name) => Some(chrom_name.to_string()),
-            None => None,
+        let (output_location, accession_ids, assembly_name) = match &args[..] {
+            [_, output_location, accession_ids, assembly_name, ..] => {
+                (output_location, accession_ids, assembly_name)
+            }
+            _ => return Err(BuildError::Config(USAGE.to_string())),
         };
+        let chromo = args.get(5).cloned();
 
-        let cov_output_file = match &chromo {
-            Some(chrom_name) => format!("level2_{}.ecd", chrom_name.strip_prefix("chr").unwrap()),
-            None => "level1.ecd".to_string(),
-        };
-        let cov_path: PathBuf = [output_location, &cov_output_file].iter().collect();
+        let mut regions: Vec<Region> = flags
+            .iter()
+            .filter_map(|flag| flag.strip_prefix("--region="))
+            .map(|region| region.parse().map_err(BuildError::Config))
+            .collect::<Result<_, _>>()?;
+        if let Some(path) = flags
+            .iter()
+            .find_map(|flag| flag.strip_prefix("--regions="))
+        {
+            regions.extend(read_bed_regions(&PathBuf::from(path)).map_err(|e| {
+                BuildError::Config(format!("Unable to read regions from {}: {}", path, e))
+            })?);
+        }
+        if !regions.is_empty() && chromo.is_some() {
+            return Err(BuildError::Config(
+                "A chromosome can't be combined with --region or --regions".to_string(),
+            ));
+        }
 
-        let feat_output_file = match &chromo {
-            Some(chrom_name) => format!("level2_{}.fd", chrom_name.strip_prefix("chr").unwrap()),
-            None => "level1.fd".to_string(),
+        let output_stem = match (&chromo, &regions[..]) {
+            (Some(chrom_name), _) => match chrom_name.strip_prefix("chr") {
+                Some(name) => format!("level2_{}", name),
+                None => {
+                    return Err(BuildError::Config(format!(
+                        "Invalid chromosome {}. Must start with \"chr\", e.g., \"chr1\"",
+                        chrom_name
+                    )))
+                }
+            },
+            (None, []) => "level1".to_string(),
+            (None, [region]) => {
+                format!(
+                    "region_{}_{}_{}",
+                    region
+                        .chrom_name
+                        .strip_prefix("chr")
+                        .unwrap_or(&region.chrom_name),
+                    region.interval.start + 1,
+                    region.interval.end + 1
+                )
+            }
+            (None, _) => "regions".to_string(),
+        };
+        let output_path = |extension: &str| -> PathBuf {
+            [output_location, &format!("{}.{}", output_stem, extension)]
+                .iter()
+                .collect()
+        };
+        let bucket_size = match args.get(4) {
+            Some(size) => match size.parse::<u32>() {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    return Err(BuildError::Config(format!(
+                        "Invalid bucket size {}. Must be a positive whole number",
+                        size
+                    )))
+                }
+            },
+            None if !regions.is_empty() => DEFAULT_REGION_BUCKET_SIZE,
+            None => DEFAULT_BUCKET_SIZE,
+        };
+        let bigbed_export = flags.iter().any(|flag| flag == "--bigbed");
+        let parquet_export = flags.iter().any(|flag| flag == "--parquet");
+        let arrow_export = flags.iter().any(|flag| flag == "--arrow");
+        if (parquet_export || arrow_export) && !cfg!(feature = "parquet") {
+            return Err(BuildError::Config(
+                "--parquet and --arrow require building with `--features parquet`".to_string(),
+            ));
+        }
+        let liftover_chain = flags
+            .iter()
+            .find_map(|flag| flag.strip_prefix("--liftover="))
+            .map(PathBuf::from);
+        let output_assembly_name = match flags
+            .iter()
+            .find_map(|flag| flag.strip_prefix("--output-assembly="))
+        {
+            // Without a liftover the coordinates stay in the input assembly
+            Some(output_assembly_name)
+                if liftover_chain.is_none() && output_assembly_name != assembly_name =>
+            {
+                return Err(BuildError::Config(format!(
+                    "--output-assembly={} needs a --liftover from {}",
+                    output_assembly_name, assembly_name
+                )))
+            }
+            Some(output_assembly_name) => output_assembly_name.to_string(),
+            None if liftover_chain.is_some() => {
+                return Err(BuildError::Config(
+                    "--liftover needs an --output-assembly".to_string(),
+                ))
+            }
+            None => assembly_name.clone(),
         };
-        let feat_path: PathBuf = [output_location, &feat_output_file].iter().collect();
-
-        Options {
-            cov_output_location: cov_path,
-            features_output_location: feat_path,
-            analysis_accession_id: args[2].clone(),
-            assembly_name: args[3].clone(),
-            bucket_size: match args.get(4) {
-                Some(size) => u32::from_str_radix(size, 10).unwrap(),
-                None => 2_000_000,
+
+        // The chromosome and region coordinates are in the output assembly, so they're checked
+        // here, before a dry run or build connects to the database. The assembly tables only
+        // have the primary chromosomes, so regions on other contigs can only be checked by the
+        // build.
+        let contig_policy = flag_value(&flags, "--contigs=", ContigPolicy::Exclude)?;
+        let assembly_info = select_assembly(&output_assembly_name)?;
+        if let Some(chromo) = &chromo {
+            let known = chromo
+                .strip_prefix("chr")
+                .map(|name| assembly_info.iter().any(|info| info.0 == name))
+                .unwrap_or(false);
+            if !known {
+                return Err(BuildError::Config(format!(
+                    "Unknown chromosome \"{}\" for assembly {}",
+                    chromo, output_assembly_name
+                )));
+            }
+        }
+        for region in &regions {
+            let chrom_length = region
+                .chrom_name
+                .strip_prefix("chr")
+                .and_then(|name| assembly_info.iter().find(|info| info.0 == name))
+                .map(|info| info.1);
+            match chrom_length {
+                Some(length) if region.interval.start >= length => {
+                    return Err(BuildError::Config(format!(
+                        "Region {} starts past the end of {} ({} bases)",
+                        region, region.chrom_name, length
+                    )))
+                }
+                None if contig_policy == ContigPolicy::Exclude => {
+                    return Err(BuildError::Config(format!(
+                        "Region {} is on {}, which isn't a chromosome of {}",
+                        region, region.chrom_name, output_assembly_name
+                    )))
+                }
+                _ => (),
+            }
+        }
+        let connection_string = env_args
+            .get(DATABASE_URL_KEY)
+            .ok_or_else(|| BuildError::Config(format!("{} is not set", DATABASE_URL_KEY)))?
+            .to_string();
+
+        Ok(Options {
+            output_directory: PathBuf::from(output_location),
+            output_stem: output_stem.clone(),
+            cov_output_location: output_path("ecd"),
+            features_output_location: output_path("fd"),
+            facet_stats_output_location: output_path("fs"),
+            summary_output_location: output_path("bs"),
+            interactions_output_location: output_path("im"),
+            analysis_accession_id: accession_ids.clone(),
+            analysis_accession_ids: split_accession_ids(accession_ids),
+            assembly_name: assembly_name.clone(),
+            bucket_size,
+            chromo,
+            regions,
+            global_ranges: flags.iter().any(|flag| flag == "--global-ranges"),
+            // BigBed files are built from the BED files, so they're always written too
+            bed_export: bigbed_export || flags.iter().any(|flag| flag == "--bed"),
+            bigbed_export,
+            bedpe_export: flags.iter().any(|flag| flag == "--bedpe"),
+            interact_export: flags.iter().any(|flag| flag == "--interact"),
+            parquet_export,
+            arrow_export,
+            compression: flag_value(&flags, "--compression=", Compression::None)?,
+            log_level: flag_value(&flags, "--log-level=", LogLevel::Info)?,
+            log_format: flag_value(&flags, "--log-format=", LogFormat::Text)?,
+            bounds_policy: flag_value(&flags, "--out-of-bounds=", BoundsPolicy::Drop)?,
+            contig_policy,
+            liftover_chain,
+            output_assembly_name,
+            dry_run: flags.iter().any(|flag| flag == "--dry-run"),
+            connection_string,
+        })
+    }
+
+    // The header of every output file of a build with these options
+    pub fn file_header(&self) -> FileHeader {
+        FileHeader::new(
+            BuildParameters {
+                analysis_accession_id: self.analysis_accession_id.clone(),
+                // The assembly of the coordinates in the file, which differs from the
+                // database's with --liftover
+                assembly_name: self.output_assembly_name.clone(),
+                bucket_size: self.bucket_size,
+                chromosome: self.chromo.clone(),
+                regions: self
+                    .regions
+                    .iter()
+                    .map(|region| region.to_string())
+                    .collect(),
             },
-            chromo: chromo,
-            connection_string: env_args.get(DATABASE_URL_KEY).unwrap().to_string(),
+            self.compression,
+        )
+    }
+
+    // Path for an extra output file, e.g., export_path("sources.bed") -> <output dir>/level1_sources.bed
+    pub fn export_path(&self, name: &str) -> PathBuf {
+        self.output_directory
+            .join(format!("{}_{}", self.output_stem, name))
+    }
+
+    // The exports a build with these options writes
+    pub fn exports(&self) -> Vec<Export> {
+        let mut exports = Vec::new();
+        if self.bed_export {
+            exports.push(Export::Bed);
+        }
+        if self.bigbed_export {
+            exports.push(Export::BigBed);
+        }
+        if self.bedpe_export {
+            exports.push(Export::Bedpe);
+        }
+        if self.interact_export {
+            exports.push(Export::Interact);
+        }
+        if self.parquet_export {
+            exports.push(Export::Parquet);
+        }
+        if self.arrow_export {
+            exports.push(Export::Arrow);
         }
+        if self.liftover_chain.is_some() {
+            exports.push(Export::Unlifted);
+        }
+        exports.push(Export::Stats);
+
+        exports
+    }
+
+    // Every file a build with these options writes, other than manifest.json
+    pub fn output_files(&self) -> Vec<PathBuf> {
+        let mut files = vec![
+            self.features_output_location.clone(),
+            self.cov_output_location.clone(),
+            self.facet_stats_output_location.clone(),
+            self.summary_output_location.clone(),
+            self.interactions_output_location.clone(),
+        ];
+        files.extend(
+            self.exports()
+                .into_iter()
+                .flat_map(|export| export.file_names())
+                .map(|name| self.export_path(name)),
+        );
+
+        files
     }
 }
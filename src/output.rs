@@ -0,0 +1,305 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use serde::{Deserialize, Serialize};
+
+use cov_viz_ds::ExperimentFeatureData;
+
+// Every file cov_viz writes starts with MAGIC followed by the format version (u32, little
+// endian), then the bincode-encoded FileHeader, then the (possibly compressed) payload. Files
+// written before the header existed are treated as format version 0.
+//
+// Version history:
+//   0: no header
+//   1: header with cov_viz version, build timestamp, build parameters, and payload compression
+pub const MAGIC: [u8; 4] = *b"CVIZ";
+pub const FORMAT_VERSION: u32 = 1;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Gzip,
+    Brotli,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "gzip" => Ok(Compression::Gzip),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(format!(
+                "Invalid compression {}. Must be \"none\", \"zstd\", \"gzip\", or \"brotli\"",
+                s
+            )),
+        }
+    }
+}
+
+impl Compression {
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BROTLI_BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW_SIZE,
+                );
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            Compression::None => decompressed.extend_from_slice(data),
+            Compression::Zstd => decompressed = zstd::decode_all(data)?,
+            Compression::Gzip => {
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            Compression::Brotli => {
+                brotli::Decompressor::new(data, BROTLI_BUFFER_SIZE)
+                    .read_to_end(&mut decompressed)?;
+            }
+        }
+
+        Ok(decompressed)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BuildParameters {
+    pub analysis_accession_id: String,
+    pub assembly_name: String,
+    pub bucket_size: u32,
+    pub chromosome: Option<String>,
+    // "chr:start-end", 1-based and closed; empty unless the build was restricted to regions
+    pub regions: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileHeader {
+    pub cov_viz_version: String,
+    // Seconds since the Unix epoch
+    pub build_timestamp: u64,
+    // Unknown for files migrated from format version 0
+    pub parameters: Option<BuildParameters>,
+    pub compression: Compression,
+}
+
+impl FileHeader {
+    // A header for a file built now, by this version of cov_viz
+    pub fn new(parameters: BuildParameters, compression: Compression) -> Self {
+        FileHeader {
+            cov_viz_version: env!("CARGO_PKG_VERSION").to_string(),
+            build_timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            parameters: Some(parameters),
+            compression,
+        }
+    }
+}
+
+pub struct OutputFile {
+    // The version the file was written with, before any migration
+    pub format_version: u32,
+    pub header: FileHeader,
+    // Always uncompressed
+    pub payload: Vec<u8>,
+}
+
+// Writes the header and payload, compressing the payload as the header says, and syncs the
+// file to disk
+pub fn write_output(path: &Path, header: &FileHeader, payload: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut writer, header).map_err(io::Error::other)?;
+    writer.write_all(&header.compression.compress(payload)?)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+// Reads a cov_viz output file, migrating older formats to the current one. Files from a
+// newer version of cov_viz are refused rather than guessed at.
+pub fn read_output(path: &Path) -> io::Result<OutputFile> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+
+    if bytes.len() < 8 || bytes[0..4] != MAGIC {
+        return Ok(OutputFile {
+            format_version: 0,
+            header: FileHeader {
+                cov_viz_version: "unknown".to_string(),
+                build_timestamp: 0,
+                parameters: None,
+                compression: Compression::None,
+            },
+            payload: bytes,
+        });
+    }
+
+    let format_version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if format_version > FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} was written with format version {}, but this cov_viz only understands up to version {}",
+                path.display(),
+                format_version,
+                FORMAT_VERSION
+            ),
+        ));
+    }
+
+    let mut rest = &bytes[8..];
+    let header: FileHeader = bincode::deserialize_from(&mut rest)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(OutputFile {
+        format_version,
+        payload: header.compression.decompress(rest)?,
+        header,
+    })
+}
+
+// ExperimentFeatureData isn't serde-serializable (the roaring bitmaps use their own format).
+// The payload is the same as ExperimentFeatureData::serialize writes: the sources bitmap
+// followed by the targets bitmap.
+pub fn feature_data_payload(features: &ExperimentFeatureData) -> io::Result<Vec<u8>> {
+    let mut payload =
+        Vec::with_capacity(features.sources.serialized_size() + features.targets.serialized_size());
+    features.sources.serialize_into(&mut payload)?;
+    features.targets.serialize_into(&mut payload)?;
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    // A file in the system temp directory that's removed when the test is done with it
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!(
+                "cov_viz_output_test_{}_{}",
+                process::id(),
+                name
+            )))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn header(compression: Compression) -> FileHeader {
+        FileHeader {
+            cov_viz_version: "1.2.3".to_string(),
+            build_timestamp: 1_700_000_000,
+            parameters: Some(BuildParameters {
+                analysis_accession_id: "DCPAN00000001".to_string(),
+                assembly_name: "GRCH38".to_string(),
+                bucket_size: 10_000,
+                chromosome: None,
+                regions: vec!["chr8:127700001-127800000".to_string()],
+            }),
+            compression,
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let file = TempFile::new("header_round_trip");
+        write_output(&file.0, &header(Compression::None), b"payload").unwrap();
+
+        let output = read_output(&file.0).unwrap();
+        assert_eq!(output.format_version, FORMAT_VERSION);
+        assert_eq!(output.header.cov_viz_version, "1.2.3");
+        assert_eq!(output.header.build_timestamp, 1_700_000_000);
+        assert_eq!(output.header.compression, Compression::None);
+        let parameters = output.header.parameters.unwrap();
+        assert_eq!(parameters.analysis_accession_id, "DCPAN00000001");
+        assert_eq!(parameters.assembly_name, "GRCH38");
+        assert_eq!(parameters.bucket_size, 10_000);
+        assert_eq!(parameters.chromosome, None);
+        assert_eq!(parameters.regions, vec!["chr8:127700001-127800000"]);
+        assert_eq!(output.payload, b"payload");
+    }
+
+    #[test]
+    fn headerless_file_is_version_0() {
+        let file = TempFile::new("headerless_file_is_version_0");
+        fs::write(&file.0, b"raw bincode").unwrap();
+
+        let output = read_output(&file.0).unwrap();
+        assert_eq!(output.format_version, 0);
+        assert!(output.header.parameters.is_none());
+        assert_eq!(output.header.compression, Compression::None);
+        assert_eq!(output.payload, b"raw bincode");
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let payload: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| (i % 97).to_le_bytes())
+            .collect();
+        for compression in [Compression::Zstd, Compression::Gzip, Compression::Brotli] {
+            let compressed = compression.compress(&payload).unwrap();
+            assert!(compressed.len() < payload.len(), "{:?}", compression);
+            assert_eq!(compression.decompress(&compressed).unwrap(), payload);
+
+            let file = TempFile::new(&format!("compression_round_trip_{:?}", compression));
+            write_output(&file.0, &header(compression), &payload).unwrap();
+            let output = read_output(&file.0).unwrap();
+            assert_eq!(output.header.compression, compression);
+            assert_eq!(output.payload, payload);
+        }
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let file = TempFile::new("newer_version_is_refused");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        bytes.extend_from_slice(&bincode::serialize(&header(Compression::None)).unwrap());
+        fs::write(&file.0, bytes).unwrap();
+
+        let error = read_output(&file.0).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
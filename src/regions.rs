@@ -0,0 +1,269 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use rustc_hash::FxHashMap;
+
+use crate::coordinates::GenomicInterval;
+
+// A region to restrict the build to, e.g., a locus of interest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    // As it appears in the database (e.g., "chr1")
+    pub chrom_name: String,
+    pub interval: GenomicInterval,
+}
+
+// Parses "chr:start-end" with 1-based, closed coordinates, as genome browsers and samtools show
+// them. Thousands separators are allowed (e.g., "chr1:1,000,001-1,100,000").
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid region {}. Must be \"<chromosome>:<start>-<end>\", e.g., \"chr1:1000001-1100000\"",
+                s
+            )
+        };
+        let (chrom_name, range) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let position = |p: &str| p.replace(',', "").parse::<i32>().map_err(|_| invalid());
+        let (start, end) = (position(start)?, position(end)?);
+        if chrom_name.is_empty() || start < 1 || end < start {
+            return Err(invalid());
+        }
+
+        Ok(Region {
+            chrom_name: chrom_name.to_string(),
+            interval: GenomicInterval {
+                start: start - 1,
+                end: end - 1,
+            },
+        })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}",
+            self.chrom_name,
+            self.interval.start + 1,
+            self.interval.end + 1
+        )
+    }
+}
+
+// Reads the regions from a BED file (0-based, half-open). Only the first three columns are
+// used; track, browser, and comment lines are skipped.
+pub fn read_bed_regions(path: &Path) -> io::Result<Vec<Region>> {
+    let mut regions = Vec::new();
+    for (idx, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty()
+            || fields[0].starts_with('#')
+            || fields[0] == "track"
+            || fields[0] == "browser"
+        {
+            continue;
+        }
+
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} line {}: malformed region", path.display(), idx + 1),
+            )
+        };
+        if fields.len() < 3 {
+            return Err(invalid());
+        }
+        let start = fields[1].parse::<i32>().map_err(|_| invalid())?;
+        let end = fields[2].parse::<i32>().map_err(|_| invalid())?;
+        if start < 0 || end <= start {
+            return Err(invalid());
+        }
+        regions.push(Region {
+            chrom_name: fields[0].to_string(),
+            interval: GenomicInterval {
+                start,
+                end: end - 1,
+            },
+        });
+    }
+
+    Ok(regions)
+}
+
+// The regions as parallel arrays of chromosome names, 0-based starts, and 0-based, exclusive
+// ends, the query parameters REO_IN_REGIONS takes
+pub fn region_columns(regions: &[Region]) -> (Vec<&str>, Vec<i32>, Vec<i32>) {
+    let chrom_names = regions
+        .iter()
+        .map(|region| region.chrom_name.as_str())
+        .collect();
+    let starts = regions.iter().map(|region| region.interval.start).collect();
+    let ends = regions
+        .iter()
+        .map(|region| region.interval.half_open().1)
+        .collect();
+    (chrom_names, starts, ends)
+}
+
+// The regions merged into sorted, disjoint intervals on each chromosome, for quick overlap checks
+pub struct RegionIndex {
+    // chromosome name -> intervals
+    chroms: FxHashMap<String, Vec<GenomicInterval>>,
+}
+
+impl RegionIndex {
+    pub fn new(regions: &[Region]) -> Self {
+        let mut chroms: FxHashMap<String, Vec<GenomicInterval>> = FxHashMap::default();
+        for region in regions {
+            chroms
+                .entry(region.chrom_name.clone())
+                .or_default()
+                .push(region.interval);
+        }
+        for intervals in chroms.values_mut() {
+            intervals.sort_by_key(|interval| interval.start);
+            let mut merged: Vec<GenomicInterval> = Vec::with_capacity(intervals.len());
+            for interval in intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if interval.start <= last.end.saturating_add(1) => {
+                        last.end = last.end.max(interval.end);
+                    }
+                    _ => merged.push(*interval),
+                }
+            }
+            *intervals = merged;
+        }
+
+        RegionIndex { chroms }
+    }
+
+    // Whether any base of the interval is in one of the regions
+    pub fn overlaps(&self, chrom_name: &str, interval: &GenomicInterval) -> bool {
+        let intervals = match self.chroms.get(chrom_name) {
+            Some(intervals) => intervals,
+            None => return false,
+        };
+        // The merged regions are disjoint, so the last one starting at or before the interval's
+        // end is the one reaching furthest
+        let end = intervals.partition_point(|region| region.start <= interval.end);
+        end > 0 && intervals[end - 1].end >= interval.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: i32, end: i32) -> GenomicInterval {
+        GenomicInterval { start, end }
+    }
+
+    fn region(chrom_name: &str, start: i32, end: i32) -> Region {
+        Region {
+            chrom_name: chrom_name.to_string(),
+            interval: interval(start, end),
+        }
+    }
+
+    #[test]
+    fn parse_region() {
+        // 1-based, closed coordinates become 0-based, closed ones
+        assert_eq!("chr1:1001-2000".parse(), Ok(region("chr1", 1000, 1999)));
+        assert_eq!(
+            "chr8:127,700,001-127,800,000".parse(),
+            Ok(region("chr8", 127_700_000, 127_799_999))
+        );
+        // A single base
+        assert_eq!("chrX:1-1".parse(), Ok(region("chrX", 0, 0)));
+        // Contig names can contain colons, so the coordinates follow the last one
+        assert_eq!(
+            "chrUn:KI270302v1:11-20".parse(),
+            Ok(region("chrUn:KI270302v1", 10, 19))
+        );
+        assert_eq!(region("chr1", 1000, 1999).to_string(), "chr1:1001-2000");
+
+        for invalid in [
+            "chr1",
+            "chr1:1000",
+            ":1-1000",
+            "chr1:0-1000",
+            "chr1:2000-1000",
+            "chr1:a-1000",
+            "chr1:-1-1000",
+        ] {
+            assert!(invalid.parse::<Region>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn bed_regions() {
+        let path = std::env::temp_dir().join(format!("cov_viz_regions_{}.bed", std::process::id()));
+        std::fs::write(
+            &path,
+            "track name=regions\n# comment\n\nchr1\t1000\t2000\tname\nchr2 0 1\n",
+        )
+        .unwrap();
+        let regions = read_bed_regions(&path);
+        std::fs::write(&path, "chr1\t2000\t1000\n").unwrap();
+        let reversed = read_bed_regions(&path);
+        std::fs::write(&path, "chr1\t1000\n").unwrap();
+        let missing_end = read_bed_regions(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // 0-based, half-open coordinates become 0-based, closed ones
+        assert_eq!(
+            regions.unwrap(),
+            vec![region("chr1", 1000, 1999), region("chr2", 0, 0)]
+        );
+        assert!(reversed.is_err());
+        assert!(missing_end.is_err());
+    }
+
+    #[test]
+    fn query_columns() {
+        let regions = [region("chr1", 1000, 1999), region("chr8", 0, 0)];
+        assert_eq!(
+            region_columns(&regions),
+            (vec!["chr1", "chr8"], vec![1000, 0], vec![2000, 1])
+        );
+    }
+
+    #[test]
+    fn overlaps() {
+        let index = RegionIndex::new(&[
+            region("chr1", 300, 399),
+            region("chr1", 100, 199),
+            // Overlaps the one before it
+            region("chr1", 150, 249),
+            // Adjacent to the one before it, so they merge
+            region("chr1", 250, 259),
+            region("chr2", 0, 9),
+        ]);
+        assert_eq!(
+            index.chroms["chr1"],
+            vec![interval(100, 259), interval(300, 399)]
+        );
+
+        assert!(index.overlaps("chr1", &interval(100, 100)));
+        assert!(index.overlaps("chr1", &interval(259, 259)));
+        assert!(index.overlaps("chr1", &interval(0, 100)));
+        assert!(index.overlaps("chr1", &interval(399, 500)));
+        // Spans the gap between the two merged regions
+        assert!(index.overlaps("chr1", &interval(250, 350)));
+        assert!(index.overlaps("chr1", &interval(0, 1000)));
+        assert!(!index.overlaps("chr1", &interval(0, 99)));
+        assert!(!index.overlaps("chr1", &interval(260, 299)));
+        assert!(!index.overlaps("chr1", &interval(400, 500)));
+        assert!(index.overlaps("chr2", &interval(9, 20)));
+        assert!(!index.overlaps("chr3", &interval(0, 1000)));
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::env;
+
+use postgres::types::Json;
+use postgres::{Client, NoTls};
+use postgres_range::Range;
+use rustc_hash::FxHashMap;
+
+use cov_viz_ds::facets::{FACET_DIRECTION, FACET_EFFECT_SIZE, FACET_SIGNIFICANCE};
+use cov_viz_ds::DbID;
+
+use crate::build_data::{select_assembly, REQUIRED_FACETS};
+use crate::coordinates::GenomicInterval;
+use crate::error::BuildError;
+use crate::options::{split_accession_ids, DATABASE_URL_KEY};
+
+struct Violation {
+    // What kind of row the violation is about, e.g., "reo" or "source"
+    kind: &'static str,
+    // None when there's no row to point at, e.g., for a missing facet
+    id: Option<DbID>,
+    problem: String,
+}
+
+// Checks a source or target location, which build_data assumes is a bounded, non-empty
+// range inside the chromosome.
+fn check_location(
+    location: Option<Range<i32>>,
+    chrom_length: Option<i32>,
+    problems: &mut Vec<String>,
+) {
+    let location = match location {
+        Some(location) => location,
+        None => {
+            problems.push("location is null".to_string());
+            return;
+        }
+    };
+    let (start, end) = match GenomicInterval::from_range(&location) {
+        Ok(interval) => interval.half_open(),
+        Err(e) => {
+            problems.push(e);
+            return;
+        }
+    };
+
+    if start < 0 {
+        problems.push(format!("location starts at negative coordinate {}", start));
+    }
+    match chrom_length {
+        Some(length) if end > length => problems.push(format!(
+            "location ends at {}, past the end of the chromosome ({})",
+            end, length
+        )),
+        _ => (),
+    }
+}
+
+// cov_viz validate <experiment accession id[,accession id...]> <assembly name>
+//
+// Runs every check build_data relies on against an analysis and reports every violation,
+// rather than stopping at the first one like the build does. Returns the number of violations.
+pub fn validate(args: &[String]) -> Result<usize, BuildError> {
+    let (accession_ids, assembly_name) = match args {
+        [accession_ids, assembly_name, ..] => (split_accession_ids(accession_ids), assembly_name),
+        _ => return Err(BuildError::Config(
+            "Usage: cov_viz validate <experiment accession id[,accession id...]> <assembly name>"
+                .to_string(),
+        )),
+    };
+    let assembly_info = select_assembly(assembly_name)?;
+    let chrom_lengths: FxHashMap<&str, i32> =
+        assembly_info.iter().map(|info| (info.0, info.1)).collect();
+
+    let connection_string = env::var(DATABASE_URL_KEY)
+        .map_err(|_| BuildError::Config(format!("{} is not set", DATABASE_URL_KEY)))?;
+    let mut client = Client::connect(&connection_string, NoTls)?;
+
+    let mut violations: Vec<Violation> = Vec::new();
+
+    for name in REQUIRED_FACETS {
+        let rows = client.query("SELECT id FROM search_facet WHERE name = $1", &[&name])?;
+        match rows.first() {
+            None => violations.push(Violation {
+                kind: "facet",
+                id: None,
+                problem: format!("required facet \"{}\" is missing", name),
+            }),
+            Some(row) if name == FACET_DIRECTION => {
+                let facet_id = row.get::<usize, i64>(0);
+                let values = client.query(
+                    "SELECT id FROM search_facetvalue WHERE facet_id = $1 AND value = 'Non-significant'",
+                    &[&facet_id],
+                )?;
+                if values.is_empty() {
+                    violations.push(Violation {
+                        kind: "facet",
+                        id: Some(facet_id as DbID),
+                        problem: "direction facet value \"Non-significant\" is missing".to_string(),
+                    });
+                }
+            }
+            Some(_) => (),
+        }
+    }
+
+    // (id: DbID, numeric facets: Json)
+    let reos = client.query(
+        r#"
+        SELECT reo.id, reo.facet_num_values, reo.analysis_accession_id
+        FROM search_regulatoryeffectobservation AS reo
+        WHERE reo.analysis_accession_id = ANY($1)"#,
+        &[&accession_ids],
+    )?;
+    for accession_id in &accession_ids {
+        if !reos
+            .iter()
+            .any(|row| row.get::<usize, &str>(2) == accession_id)
+        {
+            violations.push(Violation {
+                kind: "analysis",
+                id: None,
+                problem: format!(
+                    "analysis \"{}\" has no regulatory effect observations",
+                    accession_id
+                ),
+            });
+        }
+    }
+    for row in &reos {
+        let reo_id = row.get::<usize, i64>(0) as DbID;
+        let num_facets = row.get::<usize, Option<Json<FxHashMap<String, serde_json::Value>>>>(1);
+        // The build reads every numeric facet value as a number, so a single string or null
+        // fails it
+        if let Some(facets) = &num_facets {
+            let mut names: Vec<&String> = facets
+                .0
+                .iter()
+                .filter(|(_, value)| !value.is_number())
+                .map(|(name, _)| name)
+                .collect();
+            names.sort();
+            for name in names {
+                violations.push(Violation {
+                    kind: "reo",
+                    id: Some(reo_id),
+                    problem: format!("numeric facet {} is not a number", name),
+                });
+            }
+        }
+        for name in [FACET_EFFECT_SIZE, FACET_SIGNIFICANCE] {
+            let value = num_facets
+                .as_ref()
+                .and_then(|facets| facets.0.get(name))
+                .and_then(|value| value.as_f64());
+            if value.is_none() {
+                violations.push(Violation {
+                    kind: "reo",
+                    id: Some(reo_id),
+                    problem: format!("no numeric {} value", name),
+                });
+            }
+        }
+    }
+
+    let reos_without_sources = client.query(
+        r#"
+        SELECT reo.id
+        FROM search_regulatoryeffectobservation AS reo
+        WHERE reo.analysis_accession_id = ANY($1) AND NOT EXISTS (
+            SELECT 1 FROM search_regulatoryeffectobservation_sources AS re_s
+            WHERE re_s.regulatoryeffectobservation_id = reo.id
+        )"#,
+        &[&accession_ids],
+    )?;
+    for row in &reos_without_sources {
+        violations.push(Violation {
+            kind: "reo",
+            id: Some(row.get::<usize, i64>(0) as DbID),
+            problem: "no sources".to_string(),
+        });
+    }
+
+    let reos_with_several_targets = client.query(
+        r#"
+        SELECT reo.id, COUNT(*)
+        FROM search_regulatoryeffectobservation AS reo
+        INNER JOIN search_regulatoryeffectobservation_targets AS re_t ON (reo.id = re_t.regulatoryeffectobservation_id)
+        WHERE reo.analysis_accession_id = ANY($1)
+        GROUP BY reo.id
+        HAVING COUNT(*) > 1"#,
+        &[&accession_ids],
+    )?;
+    for row in &reos_with_several_targets {
+        violations.push(Violation {
+            kind: "reo",
+            id: Some(row.get::<usize, i64>(0) as DbID),
+            problem: format!(
+                "{} targets, but only the first is used",
+                row.get::<usize, i64>(1)
+            ),
+        });
+    }
+
+    // (feature id: DbID, chrom name: &str, location: Range(i32), strand: &str)
+    for (kind, join_table) in [
+        ("source", "search_regulatoryeffectobservation_sources"),
+        ("target", "search_regulatoryeffectobservation_targets"),
+    ] {
+        let features = client.query(
+            &format!(
+                r#"
+                SELECT DISTINCT f.id, f.chrom_name, f.location, f.strand
+                FROM search_dnafeature AS f
+                INNER JOIN {0} AS re_f ON (f.id = re_f.dnafeature_id)
+                INNER JOIN search_regulatoryeffectobservation AS reo ON (reo.id = re_f.regulatoryeffectobservation_id)
+                WHERE reo.analysis_accession_id = ANY($1)"#,
+                join_table
+            ),
+            &[&accession_ids],
+        )?;
+        for row in &features {
+            let feature_id = row.get::<usize, i64>(0) as DbID;
+            let chrom_name = row.get::<usize, Option<&str>>(1);
+            let mut problems = Vec::new();
+
+            let chrom_length = chrom_name
+                .and_then(|name| name.strip_prefix("chr"))
+                .and_then(|name| chrom_lengths.get(name))
+                .cloned();
+            if chrom_length.is_none() {
+                problems.push(format!(
+                    "chromosome {} is not in {}",
+                    chrom_name.unwrap_or("null"),
+                    assembly_name
+                ));
+            }
+            check_location(
+                row.get::<usize, Option<Range<i32>>>(2),
+                chrom_length,
+                &mut problems,
+            );
+            // The build places the TSS of a "-" strand target at its end and of any other
+            // target (including "." for unknown) at its start, but it can't read a null strand
+            if kind == "target" && row.get::<usize, Option<&str>>(3).is_none() {
+                problems.push("strand is null".to_string());
+            }
+
+            violations.extend(problems.into_iter().map(|problem| Violation {
+                kind,
+                id: Some(feature_id),
+                problem,
+            }));
+        }
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for violation in &violations {
+        match violation.id {
+            Some(id) => println!("{} {}: {}", violation.kind, id, violation.problem),
+            None => println!("{}: {}", violation.kind, violation.problem),
+        }
+        *counts.entry(violation.kind).or_insert(0) += 1;
+    }
+    println!("{} violations", violations.len());
+    for (kind, count) in counts {
+        println!("  {}: {}", kind, count);
+    }
+
+    Ok(violations.len())
+}